@@ -0,0 +1,82 @@
+use std::time::Duration;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use secp256k1zkp as secp;
+use secp::Secp256k1;
+use revelio::context::secp_context;
+use revelio::exchange::{RevelioGrinExchange, RevelioProof, Generators};
+use revelio::nizk::{RevelioPoK, PoKEncoding};
+
+const ANON_LIST_SIZE: usize = 10_000;
+const OWN_LIST_SIZE: usize = 100;
+
+/// Verifies the proof of the output at index i with the given context
+fn verify_output(secp_inst: &Secp256k1, proof: &RevelioProof, statement: &[u8], i: usize) -> bool {
+  let gens = Generators::new();
+  RevelioPoK::verify_spk(
+    secp_inst,
+    &proof.anon_list[i],
+    &proof.keyimage_list[i],
+    &gens.blinding_gen,
+    &gens.value_gen,
+    &gens.keyimage_gen,
+    statement,
+    proof.nonce.as_deref(),
+    &proof.spk_list[i],
+  ).unwrap()
+}
+
+fn bench_context(c: &mut Criterion) {
+  let mut rng = StdRng::seed_from_u64(0);
+  let mut grin_exch = RevelioGrinExchange::new(ANON_LIST_SIZE, OWN_LIST_SIZE, &mut rng).unwrap();
+  let proof = grin_exch.generate_proof(&mut rng).unwrap();
+  let statement = proof.statement_digest();
+
+  // The cost which used to be paid in every call of a prover or verifier
+  let mut group = c.benchmark_group("per_output");
+  group.bench_function("context_creation", |b| {
+    b.iter(|| Secp256k1::with_caps(secp::ContextFlag::Commit))
+  });
+  group.bench_function("verify_spk_fresh_context", |b| {
+    b.iter(|| {
+      let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+      verify_output(&secp_inst, &proof, &statement, 0)
+    })
+  });
+  group.bench_function("verify_spk_shared_context", |b| {
+    b.iter(|| verify_output(secp_context(), &proof, &statement, 0))
+  });
+  group.finish();
+
+  let mut group = c.benchmark_group("anon_list_10000");
+  group.sample_size(10);
+  group.measurement_time(Duration::from_secs(30));
+  group.throughput(Throughput::Elements(ANON_LIST_SIZE as u64));
+  group.bench_function("generate_proof", |b| {
+    b.iter(|| grin_exch.generate_proof(&mut rng).unwrap())
+  });
+  group.bench_function("verify", |b| {
+    b.iter(|| proof.verify().unwrap())
+  });
+  // All output proofs, with a context created per output as every prover
+  // and verifier call used to do, and with the shared one
+  group.bench_function("verify_outputs_fresh_context", |b| {
+    b.iter(|| (0..ANON_LIST_SIZE).all(|i| {
+      let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+      verify_output(&secp_inst, &proof, &statement, i)
+    }))
+  });
+  group.bench_function("verify_outputs_shared_context", |b| {
+    b.iter(|| (0..ANON_LIST_SIZE).all(|i| verify_output(secp_context(), &proof, &statement, i)))
+  });
+  let mut batch_proof = proof.clone();
+  batch_proof.set_encoding(PoKEncoding::Commitment).unwrap();
+  group.bench_function("verify_batch", |b| {
+    b.iter(|| batch_proof.verify().unwrap())
+  });
+  group.finish();
+}
+
+criterion_group!(benches, bench_context);
+criterion_main!(benches);
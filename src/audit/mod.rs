@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use secp256k1zkp as secp;
+use secp::key::PublicKey;
+
+use super::error::Error;
+use super::exchange::{RevelioProof, DecoySeed};
+#[cfg(doc)]
+use super::exchange::RevelioGrinExchange;
+use super::parallel;
+
+/// Outcome of checking a proof against the decoy seed of its exchange
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecoyAudit {
+  /// Indices of the outputs whose key image is derived from the seed
+  pub decoy_indices: Vec<usize>,
+  /// Indices of all other outputs, which are the exchange's own if the
+  /// disclosed seed is the one the proof was made with
+  pub own_indices: Vec<usize>,
+}
+
+/// Recomputes the decoy key image of every output of a proof from a seed
+/// disclosed by the exchange and sorts the outputs into decoys and own
+/// outputs.
+///
+/// The audit does not verify the proof, which should be done first. An own
+/// output cannot pass for a decoy, since its key image alpha*G' + beta*H would
+/// have to equal k_i*G' for the k_i derived from the seed. The auditor learns
+/// which outputs are the exchange's, but not their amounts.
+pub fn audit_decoys(proof: &RevelioProof, seed: &DecoySeed) -> Result<DecoyAudit, Error> {
+  let is_decoy = parallel::map_indices(proof.anon_list.len(), |i| -> Result<bool, Error> {
+    Ok(seed.decoy_keyimage(&proof.anon_list[i])? == proof.keyimage_list[i])
+  });
+
+  let mut audit = DecoyAudit { decoy_indices: Vec::new(), own_indices: Vec::new() };
+  for (i, result) in is_decoy.into_iter().enumerate() {
+    if result? {
+      audit.decoy_indices.push(i);
+    } else {
+      audit.own_indices.push(i);
+    }
+  }
+  Ok(audit)
+}
+
+/// Differences between two proofs of an exchange from consecutive epochs.
+/// Outputs and key images are identified by their index in either proof.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochComparison {
+  /// Indices into the new proof of the outputs missing from the old one
+  pub added_outputs: Vec<usize>,
+  /// Indices into the old proof of the outputs missing from the new one
+  pub removed_outputs: Vec<usize>,
+  /// (old index, new index) of every key image found in both proofs
+  pub persisted_keyimages: Vec<(usize, usize)>,
+  /// (old index, new index) of every output found in both proofs whose key
+  /// image differs between them
+  pub changed_keyimages: Vec<(usize, usize)>,
+  pub old_total: u64,
+  pub new_total: u64,
+}
+
+impl EpochComparison {
+  /// Change of the declared total from the old proof to the new one
+  pub fn total_change(&self) -> i128 {
+    self.new_total as i128 - self.old_total as i128
+  }
+
+  /// Whether some output in both proofs has a different key image in each.
+  ///
+  /// Own key images never change, see
+  /// [`RevelioGrinExchange::create_keyimage`], so a changed key image belongs
+  /// to a decoy whose key was derived from another seed. The outputs whose
+  /// key images persist then stand out as the exchange's own.
+  pub fn breaks_privacy(&self) -> bool {
+    !self.changed_keyimages.is_empty()
+  }
+}
+
+/// Compares two proofs of the same exchange, e.g. those of two consecutive
+/// weeks. The proofs are not verified, but proofs with different exchange
+/// ids in their contexts are rejected.
+pub fn compare_epochs(old: &RevelioProof, new: &RevelioProof) -> Result<EpochComparison, Error> {
+  if old.context.exchange_id != new.context.exchange_id {
+    return Err(Error::ExchangeMismatch {
+      old: old.context.exchange_id.clone(),
+      new: new.context.exchange_id.clone(),
+    });
+  }
+
+  let old_outputs = index_map(&old.anon_list);
+  let new_outputs = index_map(&new.anon_list);
+  let old_keyimages = index_map(&old.keyimage_list);
+
+  let mut comparison = EpochComparison {
+    added_outputs: Vec::new(),
+    removed_outputs: Vec::new(),
+    persisted_keyimages: Vec::new(),
+    changed_keyimages: Vec::new(),
+    old_total: old.total_amount,
+    new_total: new.total_amount,
+  };
+
+  for (j, output) in new.anon_list.iter().enumerate() {
+    match old_outputs.get(output) {
+      Some(&i) => {
+        if old.keyimage_list.get(i) != new.keyimage_list.get(j) {
+          comparison.changed_keyimages.push((i, j));
+        }
+      },
+      None => comparison.added_outputs.push(j),
+    }
+  }
+  comparison.removed_outputs = (0..old.anon_list.len())
+                                 .filter(|i| !new_outputs.contains_key(&old.anon_list[*i]))
+                                 .collect();
+  for (j, keyimage) in new.keyimage_list.iter().enumerate() {
+    if let Some(&i) = old_keyimages.get(keyimage) {
+      comparison.persisted_keyimages.push((i, j));
+    }
+  }
+  Ok(comparison)
+}
+
+/// Where a key image occurs among a set of proofs
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyImageOccurrence {
+  /// Label under which the caller gave the proof, naming the party which
+  /// submitted it
+  pub label: String,
+  /// Exchange id in the context of the proof
+  pub exchange_id: String,
+  /// Block height in the context of the proof
+  pub epoch: u64,
+  /// Index of the key image in the proof
+  pub index: usize,
+  /// Output at that index
+  pub output: PublicKey,
+}
+
+/// A key image which occurs in the proofs of more than one exchange, with
+/// all its occurrences
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedKeyImage {
+  pub keyimage: PublicKey,
+  pub occurrences: Vec<KeyImageOccurrence>,
+}
+
+impl SharedKeyImage {
+  /// Returns the key image with its occurrences if they carry more than one
+  /// label. This is the rule of find_shared_keyimages and of the key-image
+  /// registry.
+  ///
+  /// The exchange id is chosen by the exchange itself, so two exchanges could
+  /// hide a shared output by claiming the same id. Occurrences are therefore
+  /// told apart by the label of the party which submitted the proof.
+  pub fn from_occurrences(keyimage: PublicKey, occurrences: Vec<KeyImageOccurrence>) -> Option<SharedKeyImage> {
+    if occurrences.iter().any(|o| o.label != occurrences[0].label) {
+      Some(SharedKeyImage { keyimage, occurrences })
+    } else {
+      None
+    }
+  }
+}
+
+/// Finds every key image which occurs in the proofs of more than one
+/// exchange, e.g. among proofs of different exchanges over the same UTXO
+/// snapshot. Every proof comes with a label naming the exchange which
+/// submitted it, such as the file it was read from.
+///
+/// Two exchanges which both count an output publish the same key image for
+/// it, see [`RevelioGrinExchange::create_keyimage`], while their decoy key
+/// images do not collide. A shared key image therefore means that an output
+/// was counted towards the reserves of more than one exchange. Key images
+/// repeated only within the proofs of one label, such as those of an
+/// exchange's proofs for two epochs, are not reported. The result is ordered
+/// by the first occurrence of each key image.
+pub fn find_shared_keyimages(proofs: &[(&str, &RevelioProof)]) -> Vec<SharedKeyImage> {
+  // Occurrences of every key image, keyed by the position of the first one
+  let mut occurrences: HashMap<PublicKey, ((usize, usize), Vec<KeyImageOccurrence>)> = HashMap::new();
+  for (p, (label, proof)) in proofs.iter().enumerate() {
+    for (i, (output, keyimage)) in proof.anon_list.iter().zip(&proof.keyimage_list).enumerate() {
+      occurrences.entry(*keyimage).or_insert_with(|| ((p, i), Vec::new())).1.push(KeyImageOccurrence {
+        label: label.to_string(),
+        exchange_id: proof.context.exchange_id.clone(),
+        epoch: proof.context.block_height,
+        index: i,
+        output: *output,
+      });
+    }
+  }
+
+  let mut shared: Vec<((usize, usize), SharedKeyImage)> = occurrences.into_iter()
+    .filter_map(|(keyimage, (first, occurrences))| {
+      SharedKeyImage::from_occurrences(keyimage, occurrences).map(|shared| (first, shared))
+    })
+    .collect();
+  shared.sort_by_key(|(first, _)| *first);
+  shared.into_iter().map(|(_, shared)| shared).collect()
+}
+
+/// Maps every point of a list to its first index
+fn index_map(list: &[PublicKey]) -> HashMap<PublicKey, usize> {
+  let mut map = HashMap::with_capacity(list.len());
+  for (i, point) in list.iter().enumerate().rev() {
+    map.insert(*point, i);
+  }
+  map
+}
+
+#[cfg(test)]
+mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+  use secp256k1zkp as secp;
+  use secp::Secp256k1;
+  use secp::key::{SecretKey, PublicKey};
+  use super::{audit_decoys, compare_epochs, find_shared_keyimages};
+  use crate::error::Error;
+  use crate::exchange::ProofContext;
+  use crate::context::secp_context;
+  use crate::exchange::{RevelioGrinExchange, RevelioProof, DecoySeed, OwnOutput};
+
+  /// Creates n random outputs, the last of which is owned with the given amount
+  fn random_outputs(n: usize, amount: u64, rng: &mut StdRng) -> (Vec<PublicKey>, OwnOutput) {
+    let secp_inst = secp_context();
+    let mut outputs = vec![];
+    for _i in 0..n - 1 {
+      let sk = SecretKey::new(secp_inst, rng);
+      outputs.push(PublicKey::from_secret_key(secp_inst, &sk).unwrap());
+    }
+    let blinding = SecretKey::new(secp_inst, rng);
+    let own_output = Secp256k1::commit(secp_inst, amount, blinding.clone()).unwrap()
+                       .to_pubkey(secp_inst).unwrap();
+    outputs.push(own_output);
+    (outputs, (own_output, blinding, amount))
+  }
+
+  fn prove(anon_list: &[PublicKey], own_outputs: Vec<OwnOutput>, seed: &DecoySeed, rng: &mut StdRng) -> RevelioProof {
+    let mut grin_exch = RevelioGrinExchange::from_outputs(anon_list.to_vec(), own_outputs, seed.clone(), rng).unwrap();
+    grin_exch.generate_proof(rng).unwrap()
+  }
+
+  #[test]
+  fn disclosed_seed_reveals_own_outputs() {
+    let mut rng = StdRng::seed_from_u64(24);
+    let secp_inst = secp_context();
+
+    let mut anon_list = vec![];
+    for _i in 0..10 {
+      let sk = SecretKey::new(secp_inst, &mut rng);
+      anon_list.push(PublicKey::from_secret_key(secp_inst, &sk).unwrap());
+    }
+    let blinding = SecretKey::new(secp_inst, &mut rng);
+    let own_output = Secp256k1::commit(secp_inst, 7, blinding.clone()).unwrap()
+                       .to_pubkey(secp_inst).unwrap();
+    anon_list.insert(3, own_output);
+
+    let seed = DecoySeed::random(&mut rng);
+    let mut grin_exch = RevelioGrinExchange::from_outputs(
+                          anon_list, vec![(own_output, blinding, 7)], seed.clone(), &mut rng).unwrap();
+    let proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(proof.verify().is_ok());
+
+    // The exported seed round-trips and tells the own output apart
+    let exported = DecoySeed::from_hex(&grin_exch.decoy_seed().to_hex()).unwrap();
+    assert!(exported == seed);
+    let audit = audit_decoys(&proof, &exported).unwrap();
+    assert!(audit.own_indices == vec![3]);
+    assert!(audit.decoy_indices.len() == 10);
+
+    // Any other seed matches no decoy
+    let audit = audit_decoys(&proof, &DecoySeed::random(&mut rng)).unwrap();
+    assert!(audit.decoy_indices.is_empty());
+    assert!(DecoySeed::from_hex("00ff").is_err());
+  }
+
+  #[test]
+  fn compare_consecutive_epochs() {
+    let mut rng = StdRng::seed_from_u64(26);
+    let (outputs, own1) = random_outputs(20, 9, &mut rng);
+    let (_, own2) = random_outputs(1, 4, &mut rng);
+    let seed = DecoySeed::random(&mut rng);
+
+    // Outputs 0..5 are spent and own2 is received between the two epochs
+    let old = prove(&outputs, vec![own1.clone()], &seed, &mut rng);
+    let new = prove(&outputs[5..], vec![own1.clone(), own2.clone()], &seed, &mut rng);
+    let comparison = compare_epochs(&old, &new).unwrap();
+    assert!(comparison.added_outputs == vec![new.anon_list.iter().position(|c| *c == own2.0).unwrap()]);
+    assert!(comparison.removed_outputs == vec![0, 1, 2, 3, 4]);
+    assert!(comparison.persisted_keyimages.len() == 15);
+    assert!(comparison.persisted_keyimages.iter().all(|&(i, j)| old.anon_list[i] == new.anon_list[j]));
+    assert!(comparison.total_change() == 4);
+    assert!(!comparison.breaks_privacy());
+
+    // Proofs of another exchange are not compared
+    let mut other = new.clone();
+    other.context.exchange_id = "other".to_string();
+    assert!(compare_epochs(&old, &other).err() ==
+            Some(Error::ExchangeMismatch { old: String::new(), new: "other".to_string() }));
+
+    // With another seed only the key image of own1 persists
+    let new = prove(&outputs[5..], vec![own1.clone(), own2], &DecoySeed::random(&mut rng), &mut rng);
+    let comparison = compare_epochs(&old, &new).unwrap();
+    assert!(comparison.breaks_privacy());
+    assert!(comparison.changed_keyimages.len() == 14);
+    assert!(comparison.persisted_keyimages.len() == 1);
+    assert!(old.anon_list[comparison.persisted_keyimages[0].0] == own1.0);
+  }
+
+  #[test]
+  fn detect_outputs_counted_twice() {
+    let mut rng = StdRng::seed_from_u64(27);
+    let (mut snapshot, own1) = random_outputs(15, 3, &mut rng);
+    let (_, own2) = random_outputs(1, 6, &mut rng);
+    let (_, own3) = random_outputs(1, 8, &mut rng);
+    snapshot.push(own2.0);
+    snapshot.push(own3.0);
+
+    // exch-b also claims own2, whose opening it got from exch-a
+    let owners = [("exch-a", vec![own1, own2.clone()]), ("exch-b", vec![own2.clone()]), ("exch-c", vec![own3])];
+    let mut proofs = vec![];
+    for (exchange_id, own_outputs) in owners.iter() {
+      let seed = DecoySeed::random(&mut rng);
+      let mut grin_exch = RevelioGrinExchange::from_outputs(snapshot.clone(), own_outputs.clone(), seed, &mut rng).unwrap();
+      grin_exch.set_context(ProofContext { exchange_id: exchange_id.to_string(), block_height: 7 });
+      let proof = grin_exch.generate_proof(&mut rng).unwrap();
+      assert!(proof.verify().is_ok());
+      proofs.push(proof);
+    }
+
+    let labeled: Vec<(&str, &RevelioProof)> = owners.iter().map(|(id, _)| *id).zip(&proofs).collect();
+    let shared = find_shared_keyimages(&labeled);
+    assert!(shared.len() == 1);
+    let occurrences = &shared[0].occurrences;
+    assert!(occurrences.iter().map(|o| o.label.as_str()).collect::<Vec<_>>() == vec!["exch-a", "exch-b"]);
+    assert!(occurrences.iter().all(|o| o.output == own2.0));
+    assert!(find_shared_keyimages(&labeled[1..]).is_empty());
+
+    // Claiming the exchange id of exch-a does not hide exch-b
+    proofs[1].context.exchange_id = "exch-a".to_string();
+    let labeled: Vec<(&str, &RevelioProof)> = owners.iter().map(|(id, _)| *id).zip(&proofs).collect();
+    assert!(find_shared_keyimages(&labeled).len() == 1);
+  }
+
+  #[test]
+  fn epochs_of_one_exchange_share_nothing() {
+    let mut rng = StdRng::seed_from_u64(29);
+    let (snapshot, own) = random_outputs(15, 3, &mut rng);
+    let seed = DecoySeed::random(&mut rng);
+
+    // Every key image repeats, but all of them come from the same exchange
+    let proofs = [prove(&snapshot, vec![own.clone()], &seed, &mut rng), prove(&snapshot, vec![own], &seed, &mut rng)];
+    assert!(proofs[0].keyimage_list == proofs[1].keyimage_list);
+    assert!(find_shared_keyimages(&[("exch", &proofs[0]), ("exch", &proofs[1])]).is_empty());
+  }
+}
@@ -0,0 +1,169 @@
+extern crate structopt;
+extern crate revelio;
+use structopt::StructOpt;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use revelio::audit::{audit_decoys, compare_epochs, find_shared_keyimages};
+use revelio::exchange::{RevelioProof, DecoySeed};
+use revelio::json::point_to_hex;
+use revelio::ser;
+
+/// A file could not be read
+const EXIT_IO_ERROR: i32 = 2;
+/// A proof or seed file is malformed
+const EXIT_MALFORMED: i32 = 3;
+/// An output has different key images in the compared proofs
+const EXIT_KEYIMAGES_CHANGED: i32 = 4;
+/// A key image occurs in the proofs of more than one exchange
+const EXIT_SHARED_KEYIMAGES: i32 = 5;
+/// The compared proofs come from different exchanges
+const EXIT_EXCHANGE_MISMATCH: i32 = 6;
+/// The proofs searched for collusion were not made over the same snapshot
+const EXIT_SNAPSHOT_MISMATCH: i32 = 7;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "revelio-audit", about = "Audits serialized Revelio proofs.")]
+enum Command {
+  /// Lists the outputs of a proof which are not decoys, given the decoy seed
+  /// disclosed by the exchange
+  #[structopt(name = "decoys")]
+  Decoys {
+    /// Proof file in the binary or the JSON encoding
+    #[structopt(parse(from_os_str))]
+    proof_file: PathBuf,
+    /// File holding the hex-encoded decoy seed of the exchange
+    #[structopt(short = "s", long = "seed-file", parse(from_os_str))]
+    seed_file: PathBuf,
+  },
+  /// Compares the proofs of an exchange from two epochs. The proofs are not
+  /// verified.
+  #[structopt(name = "compare")]
+  Compare {
+    /// Proof of the earlier epoch
+    #[structopt(parse(from_os_str))]
+    old_proof_file: PathBuf,
+    /// Proof of the later epoch
+    #[structopt(parse(from_os_str))]
+    new_proof_file: PathBuf,
+  },
+  /// Finds key images shared by the proofs of several exchanges over the
+  /// same UTXO snapshot, i.e. outputs counted by more than one exchange.
+  /// Each file is taken to come from a different exchange.
+  #[structopt(name = "collusion")]
+  Collusion {
+    /// Proof files of the exchanges, all at the same block height and with
+    /// the same anonymity list up to its order
+    #[structopt(parse(from_os_str), required = true)]
+    proof_files: Vec<PathBuf>,
+  },
+}
+
+fn fail(code: i32, msg: String) -> ! {
+  println!("Error: {}", msg);
+  process::exit(code);
+}
+
+fn read_file(path: &PathBuf) -> Vec<u8> {
+  match fs::read(path) {
+    Ok(bytes) => bytes,
+    Err(e) => fail(EXIT_IO_ERROR, format!("cannot read {}: {}", path.display(), e)),
+  }
+}
+
+/// Reads a Revelio proof, treating input which starts with '{' as JSON
+fn read_proof(path: &PathBuf) -> RevelioProof {
+  let bytes = read_file(path);
+  let result = if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+      .and_then(|text| RevelioProof::from_json(&text).map_err(|e| e.to_string()))
+  } else {
+    ser::deserialize(&bytes).map_err(|e| e.to_string())
+  };
+  match result {
+    Ok(proof) => proof,
+    Err(e) => fail(EXIT_MALFORMED, format!("malformed proof {}: {}", path.display(), e)),
+  }
+}
+
+fn main() {
+    match Command::from_args() {
+      Command::Decoys { proof_file, seed_file } => {
+        let proof = read_proof(&proof_file);
+        let text = String::from_utf8_lossy(&read_file(&seed_file)).into_owned();
+        let seed = match DecoySeed::from_hex(&text) {
+          Ok(seed) => seed,
+          Err(e) => fail(EXIT_MALFORMED, format!("malformed seed: {}", e)),
+        };
+        let audit = match audit_decoys(&proof, &seed) {
+          Ok(audit) => audit,
+          Err(e) => fail(EXIT_MALFORMED, e.to_string()),
+        };
+        println!("Anonymity list size = {}", proof.anon_list.len());
+        println!("Decoy outputs = {}", audit.decoy_indices.len());
+        println!("Own outputs = {}", audit.own_indices.len());
+        println!("Own output indices = {:?}", audit.own_indices);
+      },
+      Command::Compare { old_proof_file, new_proof_file } => {
+        let old = read_proof(&old_proof_file);
+        let new = read_proof(&new_proof_file);
+        let comparison = match compare_epochs(&old, &new) {
+          Ok(comparison) => comparison,
+          Err(e) => fail(EXIT_EXCHANGE_MISMATCH, e.to_string()),
+        };
+        for (name, proof) in &[("Old", &old), ("New", &new)] {
+          println!("{} proof = exchange id {:?}, block height {}, {} outputs, total amount {}",
+            name, proof.context.exchange_id, proof.context.block_height,
+            proof.anon_list.len(), proof.total_amount);
+        }
+        println!("Added outputs = {}", comparison.added_outputs.len());
+        println!("Removed outputs = {}", comparison.removed_outputs.len());
+        println!("Persisted key images = {}", comparison.persisted_keyimages.len());
+        println!("Total change = {:+}", comparison.total_change());
+        if comparison.breaks_privacy() {
+          println!("Changed key images = {:?} (old index, new index)", comparison.changed_keyimages);
+          println!("Warning: decoy key images changed, so the outputs whose key images persisted stand out as own outputs");
+          process::exit(EXIT_KEYIMAGES_CHANGED);
+        }
+      },
+      Command::Collusion { proof_files } => {
+        let proofs: Vec<RevelioProof> = proof_files.iter().map(read_proof).collect();
+        for (path, proof) in proof_files.iter().zip(&proofs) {
+          println!("{} = exchange id {:?}, block height {}, {} outputs, total amount {}",
+            path.display(), proof.context.exchange_id, proof.context.block_height,
+            proof.anon_list.len(), proof.total_amount);
+        }
+        // Outputs spent or created between two snapshots would hide or fake
+        // shared key images, so the proofs must cover the same UTXO set
+        let height = proofs[0].context.block_height;
+        if let Some(p) = proofs.iter().position(|proof| proof.context.block_height != height) {
+          fail(EXIT_SNAPSHOT_MISMATCH, format!("{} was made at block height {}, {} at {}",
+            proof_files[p].display(), proofs[p].context.block_height, proof_files[0].display(), height));
+        }
+        let snapshot: HashSet<_> = proofs[0].anon_list.iter().collect();
+        if let Some(p) = proofs.iter().position(|proof| {
+          proof.anon_list.len() != snapshot.len() || !proof.anon_list.iter().all(|output| snapshot.contains(output))
+        }) {
+          fail(EXIT_SNAPSHOT_MISMATCH, format!("the anonymity lists of {} and {} differ",
+            proof_files[p].display(), proof_files[0].display()));
+        }
+
+        let labels: Vec<String> = proof_files.iter().map(|path| path.display().to_string()).collect();
+        let labeled: Vec<(&str, &RevelioProof)> = labels.iter().map(String::as_str).zip(&proofs).collect();
+        let shared = find_shared_keyimages(&labeled);
+        println!("Shared key images = {}", shared.len());
+        for keyimage in &shared {
+          println!("Key image {} occurs in:", point_to_hex(&keyimage.keyimage));
+          for occurrence in &keyimage.occurrences {
+            println!("  {}, exchange id {:?}, block height {}, index {}, output {}",
+              occurrence.label, occurrence.exchange_id, occurrence.epoch, occurrence.index, point_to_hex(&occurrence.output));
+          }
+        }
+        if !shared.is_empty() {
+          process::exit(EXIT_SHARED_KEYIMAGES);
+        }
+      },
+    }
+}
@@ -0,0 +1,143 @@
+extern crate structopt;
+extern crate revelio;
+use structopt::StructOpt;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use revelio::json::{point_from_hex, point_to_hex};
+use revelio::registry::{KeyImageRegistry, RegistryEntry};
+
+/// The registry or a proof file could not be read or written
+const EXIT_IO_ERROR: i32 = 2;
+/// A proof or point is malformed
+const EXIT_MALFORMED: i32 = 3;
+/// A proof does not verify, is already registered or uses the exchange id of
+/// another submitter
+const EXIT_REJECTED: i32 = 4;
+/// A key image occurs in the proofs of more than one exchange
+const EXIT_SHARED_KEYIMAGES: i32 = 5;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "revelio-registry", about = "Records the key images of Revelio proofs in an SQLite database.")]
+struct Opt {
+  /// Registry database, created if it does not exist
+  #[structopt(short = "d", long = "db", parse(from_os_str))]
+  db: PathBuf,
+  #[structopt(subcommand)]
+  command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+  /// Verifies proofs and records their key images
+  #[structopt(name = "ingest")]
+  Ingest {
+    /// Name of the party submitting the proofs. An exchange id registered by
+    /// one submitter is refused to all others.
+    #[structopt(short = "s", long = "submitter")]
+    submitter: String,
+    /// Proof files in the binary or the JSON encoding
+    #[structopt(parse(from_os_str), required = true)]
+    proof_files: Vec<PathBuf>,
+  },
+  /// Lists the registered proofs
+  #[structopt(name = "proofs")]
+  Proofs,
+  /// Lists the proofs which contain a hex-encoded key image
+  #[structopt(name = "keyimage")]
+  KeyImage {
+    keyimage: String,
+  },
+  /// Lists the key images published for a hex-encoded output
+  #[structopt(name = "output")]
+  Output {
+    output: String,
+  },
+  /// Lists the key images which occur in the proofs of more than one submitter
+  #[structopt(name = "shared")]
+  Shared {
+    /// Only consider the proofs of this epoch
+    #[structopt(short = "e", long = "epoch")]
+    epoch: Option<u64>,
+  },
+}
+
+fn fail(code: i32, msg: String) -> ! {
+  println!("Error: {}", msg);
+  process::exit(code);
+}
+
+fn print_entries(entries: &[RegistryEntry]) {
+  for entry in entries {
+    println!("  submitter {:?}, exchange id {:?}, epoch {}, index {}, key image {}, output {}",
+      entry.submitter, entry.exchange_id, entry.epoch, entry.index,
+      point_to_hex(&entry.keyimage), point_to_hex(&entry.output));
+  }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let mut registry = match KeyImageRegistry::open(&opt.db) {
+      Ok(registry) => registry,
+      Err(e) => fail(EXIT_IO_ERROR, format!("cannot open {}: {}", opt.db.display(), e)),
+    };
+
+    let result = match opt.command {
+      Command::Ingest { submitter, proof_files } => {
+        for path in &proof_files {
+          let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => fail(EXIT_IO_ERROR, format!("cannot read {}: {}", path.display(), e)),
+          };
+          match registry.ingest_bytes(&submitter, &bytes) {
+            Ok(()) => println!("Registered {}", path.display()),
+            Err(e @ revelio::registry::Error::Ser(_)) | Err(e @ revelio::registry::Error::Json(_)) =>
+              fail(EXIT_MALFORMED, format!("malformed proof {}: {}", path.display(), e)),
+            Err(e @ revelio::registry::Error::Db(_)) => fail(EXIT_IO_ERROR, e.to_string()),
+            Err(e) => fail(EXIT_REJECTED, format!("{} rejected: {}", path.display(), e)),
+          }
+        }
+        Ok(())
+      },
+      Command::Proofs => registry.proofs().map(|proofs| {
+        for proof in proofs {
+          println!("submitter {:?}, exchange id {:?}, epoch {}, {} outputs, total amount {}, statement {}",
+            proof.submitter, proof.exchange_id, proof.epoch, proof.anon_list_size, proof.total_amount,
+            hex::encode(proof.statement_digest));
+        }
+      }),
+      Command::KeyImage { keyimage } => {
+        let keyimage = point_from_hex(keyimage.trim())
+                         .unwrap_or_else(|e| fail(EXIT_MALFORMED, format!("malformed key image: {}", e)));
+        registry.find_keyimage(&keyimage).map(|entries| {
+          println!("Occurrences = {}", entries.len());
+          print_entries(&entries);
+        })
+      },
+      Command::Output { output } => {
+        let output = point_from_hex(output.trim())
+                       .unwrap_or_else(|e| fail(EXIT_MALFORMED, format!("malformed output: {}", e)));
+        registry.find_output(&output).map(|entries| {
+          println!("Occurrences = {}", entries.len());
+          print_entries(&entries);
+        })
+      },
+      Command::Shared { epoch } => registry.shared_keyimages(epoch).map(|shared| {
+        println!("Shared key images = {}", shared.len());
+        for keyimage in &shared {
+          println!("Key image {} occurs in:", point_to_hex(&keyimage.keyimage));
+          for occurrence in &keyimage.occurrences {
+            println!("  submitter {:?}, exchange id {:?}, epoch {}, index {}, output {}",
+              occurrence.label, occurrence.exchange_id, occurrence.epoch, occurrence.index, point_to_hex(&occurrence.output));
+          }
+        }
+        if !shared.is_empty() {
+          process::exit(EXIT_SHARED_KEYIMAGES);
+        }
+      }),
+    };
+    if let Err(e) = result {
+      fail(EXIT_IO_ERROR, e.to_string());
+    }
+}
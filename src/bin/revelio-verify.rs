@@ -0,0 +1,227 @@
+extern crate structopt;
+extern crate revelio;
+use structopt::StructOpt;
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use std::process;
+use revelio::exchange::{RevelioProof, SimpleProof};
+use revelio::json::point_from_hex;
+use revelio::ser::{self, REVELIO_PROOF_TAG, REVELIO_STREAM_TAG, SIMPLE_PROOF_TAG};
+use revelio::stream::{StreamReader, StreamingVerifier};
+use secp256k1zkp::key::PublicKey;
+
+/// The proof is valid
+const EXIT_VALID: i32 = 0;
+/// A file could not be read
+const EXIT_IO_ERROR: i32 = 2;
+/// The proof or snapshot file is malformed
+const EXIT_MALFORMED: i32 = 3;
+/// The proof refers to outputs which are not in the snapshot
+const EXIT_NOT_IN_SNAPSHOT: i32 = 4;
+/// The proof failed verification
+const EXIT_INVALID: i32 = 5;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "revelio-verify", about = "Verifies a serialized Revelio or simple reserve proof.")]
+struct Opt {
+  /// Proof file in the binary, the JSON or the stream encoding
+  #[structopt(parse(from_os_str))]
+  proof_file: PathBuf,
+  /// File listing the outputs in the UTXO set, one hex commitment per line
+  #[structopt(short = "s", long = "snapshot", parse(from_os_str))]
+  snapshot_file: Option<PathBuf>,
+  /// Hex-encoded nonce which a Revelio proof must have been created for
+  #[structopt(long = "nonce", parse(try_from_str = "hex::decode"))]
+  nonce: Option<Vec<u8>>,
+}
+
+enum Proof {
+  Simple(SimpleProof),
+  Revelio(RevelioProof),
+}
+
+fn fail(code: i32, msg: String) -> ! {
+  println!("Result = FAIL ({})", msg);
+  process::exit(code);
+}
+
+fn read_file(path: &PathBuf) -> Vec<u8> {
+  match fs::read(path) {
+    Ok(bytes) => bytes,
+    Err(e) => fail(EXIT_IO_ERROR, format!("cannot read {}: {}", path.display(), e)),
+  }
+}
+
+/// Checks whether the file holds a proof stream, which is verified without
+/// reading the whole file into memory
+fn is_stream(path: &PathBuf) -> bool {
+  let mut prefix = [0u8; 2];
+  match File::open(path).and_then(|mut file| file.read_exact(&mut prefix)) {
+    Ok(()) => prefix[1] == REVELIO_STREAM_TAG,
+    Err(_) => false,
+  }
+}
+
+fn load_snapshot(path: &PathBuf) -> HashSet<PublicKey> {
+  let snapshot = match decode_snapshot(&read_file(path)) {
+    Ok(snapshot) => snapshot,
+    Err(e) => fail(EXIT_MALFORMED, format!("malformed snapshot: {}", e)),
+  };
+  println!("Snapshot size = {}", snapshot.len());
+  snapshot
+}
+
+/// Verifies a proof stream record by record. Records are checked against the
+/// snapshot as they arrive, so only the first missing output is reported.
+fn verify_stream_file(opt: &Opt) -> ! {
+  let file = match File::open(&opt.proof_file) {
+    Ok(file) => file,
+    Err(e) => fail(EXIT_IO_ERROR, format!("cannot read {}: {}", opt.proof_file.display(), e)),
+  };
+  let mut records = match StreamReader::new(BufReader::new(file)) {
+    Ok(records) => records,
+    Err(e) => fail(EXIT_MALFORMED, format!("malformed proof: {}", e)),
+  };
+
+  let header = records.header().clone();
+  println!("Proof type = revelio stream");
+  println!("Exchange id = {}", header.context.exchange_id);
+  println!("Block height = {}", header.context.block_height);
+  if let Some(ref nonce) = header.nonce {
+    println!("Nonce = {}", hex::encode(nonce));
+  }
+  println!("Anonymity list size = {}", header.anon_list_size);
+  println!("Total amount = {}", header.total_amount);
+
+  let snapshot = opt.snapshot_file.as_ref().map(load_snapshot);
+  if opt.nonce.is_some() && header.nonce != opt.nonce {
+    fail(EXIT_INVALID, "proof was not created for the given nonce".to_string());
+  }
+
+  let mut verifier = match StreamingVerifier::new(header) {
+    Ok(verifier) => verifier,
+    Err(e) => fail(EXIT_INVALID, e.to_string()),
+  };
+  for (i, record) in (&mut records).enumerate() {
+    let record = match record {
+      Ok(record) => record,
+      Err(e) => fail(EXIT_MALFORMED, format!("malformed proof: {}", e)),
+    };
+    if snapshot.as_ref().is_some_and(|snapshot| !snapshot.contains(&record.output)) {
+      fail(EXIT_NOT_IN_SNAPSHOT, format!("output at index {} is not in the snapshot", i));
+    }
+    if let Err(e) = verifier.push(record) {
+      fail(EXIT_INVALID, e.to_string());
+    }
+  }
+  if let Err(e) = verifier.finish() {
+    fail(EXIT_INVALID, e.to_string());
+  }
+
+  println!("Result = PASS");
+  process::exit(EXIT_VALID);
+}
+
+/// Decodes a proof, treating input which starts with '{' as JSON
+fn decode_proof(bytes: &[u8]) -> Result<Proof, String> {
+  let is_json = bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{');
+  if is_json {
+    let text = String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())?;
+    let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    match value["proof_type"].as_str() {
+      Some(revelio::json::REVELIO_PROOF_TYPE) =>
+        RevelioProof::from_json(&text).map(Proof::Revelio).map_err(|e| e.to_string()),
+      Some(revelio::json::SIMPLE_PROOF_TYPE) =>
+        SimpleProof::from_json(&text).map(Proof::Simple).map_err(|e| e.to_string()),
+      _ => Err("unknown proof type".to_string()),
+    }
+  } else {
+    match bytes.get(1) {
+      Some(&REVELIO_PROOF_TAG) =>
+        ser::deserialize(bytes).map(Proof::Revelio).map_err(|e| e.to_string()),
+      Some(&SIMPLE_PROOF_TAG) =>
+        ser::deserialize(bytes).map(Proof::Simple).map_err(|e| e.to_string()),
+      _ => Err("unknown proof type".to_string()),
+    }
+  }
+}
+
+/// Reads a snapshot file with one hex commitment per line, ignoring empty
+/// lines and lines starting with '#'
+fn decode_snapshot(bytes: &[u8]) -> Result<HashSet<PublicKey>, String> {
+  let text = String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())?;
+  let mut snapshot = HashSet::new();
+  for (lineno, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let output = point_from_hex(line).map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+    snapshot.insert(output);
+  }
+  Ok(snapshot)
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if is_stream(&opt.proof_file) {
+      verify_stream_file(&opt);
+    }
+
+    let proof = match decode_proof(&read_file(&opt.proof_file)) {
+      Ok(proof) => proof,
+      Err(e) => fail(EXIT_MALFORMED, format!("malformed proof: {}", e)),
+    };
+
+    let outputs = match proof {
+      Proof::Simple(ref simple_proof) => {
+        println!("Proof type = simple");
+        println!("Own list size = {}", simple_proof.own_list.len());
+        println!("Total amount = {}", simple_proof.total_amount);
+        &simple_proof.own_list
+      },
+      Proof::Revelio(ref revelio_proof) => {
+        println!("Proof type = revelio");
+        println!("Exchange id = {}", revelio_proof.context.exchange_id);
+        println!("Block height = {}", revelio_proof.context.block_height);
+        if let Some(ref nonce) = revelio_proof.nonce {
+          println!("Nonce = {}", hex::encode(nonce));
+        }
+        println!("Anonymity list size = {}", revelio_proof.anon_list.len());
+        println!("Total amount = {}", revelio_proof.total_amount);
+        &revelio_proof.anon_list
+      },
+    };
+
+    if let Some(ref path) = opt.snapshot_file {
+      let snapshot = load_snapshot(path);
+      let missing: Vec<usize> = (0..outputs.len()).filter(|&i| !snapshot.contains(&outputs[i])).collect();
+      if !missing.is_empty() {
+        fail(EXIT_NOT_IN_SNAPSHOT, format!("outputs at indices {:?} are not in the snapshot", missing));
+      }
+    }
+
+    if let Some(ref nonce) = opt.nonce {
+      let answered = match proof {
+        Proof::Simple(_) => false,
+        Proof::Revelio(ref revelio_proof) => revelio_proof.nonce.as_ref() == Some(nonce),
+      };
+      if !answered {
+        fail(EXIT_INVALID, "proof was not created for the given nonce".to_string());
+      }
+    }
+
+    let result = match proof {
+      Proof::Simple(ref simple_proof) => simple_proof.verify(),
+      Proof::Revelio(ref revelio_proof) => revelio_proof.verify(),
+    };
+    if let Err(e) = result {
+      fail(EXIT_INVALID, e.to_string());
+    }
+
+    println!("Result = PASS");
+    process::exit(EXIT_VALID);
+}
@@ -2,8 +2,17 @@ extern crate structopt;
 extern crate revelio;
 use structopt::StructOpt;
 
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
 use std::time::{Instant, Duration};
-use revelio::exchange::RevelioGrinExchange;
+use rand::thread_rng;
+use revelio::ser::serialize;
+use revelio::exchange::{RevelioGrinExchange, ProofContext, DecoySeed};
+use revelio::nizk::PoKEncoding;
+use revelio::parallel;
+use revelio::stream::write_stream;
+use revelio::transcript::NonceMode;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "revelio", about = "Revelio proof generation simulator.")]
@@ -14,14 +23,73 @@ struct Opt {
   own_list_size: usize,
   #[structopt(short = "n", long = "numiter", default_value = "1")]
   num_iter: u32,
+  /// Exchange id the proofs are bound to
+  #[structopt(short = "e", long = "exchange-id", default_value = "")]
+  exchange_id: String,
+  /// Block height the proofs are bound to
+  #[structopt(short = "b", long = "height", default_value = "0")]
+  block_height: u64,
+  /// Hex-encoded verifier nonce the proofs are bound to
+  #[structopt(long = "nonce", parse(try_from_str = "hex::decode"))]
+  nonce: Option<Vec<u8>>,
+  /// Derive the prover's random scalars deterministically from the secrets
+  #[structopt(short = "d", long = "deterministic")]
+  deterministic: bool,
+  /// Include the commitments in the output proofs so that they are verified as a batch
+  #[structopt(short = "c", long = "commitments")]
+  commitments: bool,
+  /// Number of threads to spread the outputs over, 0 for one per CPU
+  /// (needs the parallel feature)
+  #[structopt(short = "t", long = "threads", default_value = "0")]
+  num_threads: usize,
+  /// Write the last proof to this file, as JSON if it ends in .json and as a
+  /// stream of output records if it ends in .stream
+  #[structopt(short = "w", long = "write", parse(from_os_str))]
+  output_file: Option<PathBuf>,
+  /// Read the hex-encoded decoy seed from this file if it exists, otherwise
+  /// draw a fresh seed and write it there, e.g. for an auditor
+  #[structopt(long = "seed-file", parse(from_os_str))]
+  seed_file: Option<PathBuf>,
 }
 
 fn main() {
     let opt = Opt::from_args();
 
     let num_iter = opt.num_iter;
-    let mut grin_exch = RevelioGrinExchange::new(opt.anon_list_size, opt.own_list_size);
-    let mut revelio_proof;
+    parallel::set_num_threads(opt.num_threads).unwrap();
+    let mut rng = thread_rng();
+    let stored_seed = match opt.seed_file {
+      Some(ref path) if path.exists() => {
+        let hex = fs::read_to_string(path).unwrap();
+        Some(DecoySeed::from_hex(hex.trim()).unwrap())
+      },
+      _ => None,
+    };
+    let seed_loaded = stored_seed.is_some();
+    let mut grin_exch = match stored_seed {
+      Some(seed) => RevelioGrinExchange::new_with_seed(opt.anon_list_size, opt.own_list_size, seed, &mut rng),
+      None => RevelioGrinExchange::new(opt.anon_list_size, opt.own_list_size, &mut rng),
+    }.unwrap();
+    grin_exch.set_context(ProofContext {
+      exchange_id: opt.exchange_id.clone(),
+      block_height: opt.block_height,
+    });
+    grin_exch.set_nonce(opt.nonce.clone());
+    if opt.deterministic {
+      grin_exch.set_nonce_mode(NonceMode::Deterministic);
+    }
+    if opt.commitments {
+      grin_exch.set_encoding(PoKEncoding::Commitment);
+    }
+    if let Some(ref path) = opt.seed_file {
+      if seed_loaded {
+        println!("Decoy seed read from {}", path.display());
+      } else {
+        fs::write(path, grin_exch.decoy_seed().to_hex()).unwrap();
+        println!("Decoy seed written to {}", path.display());
+      }
+    }
+    let mut revelio_proof = None;
     let mut gen_proof_start;
     let mut gen_proof_end;
     let mut ver_proof_start;
@@ -33,14 +101,15 @@ fn main() {
 
     for _i in 0..num_iter {
       gen_proof_start = Instant::now();
-      revelio_proof = grin_exch.generate_proof();
+      let proof = grin_exch.generate_proof(&mut rng).unwrap();
       gen_proof_end = Instant::now();
       total_gen_proof_duration += gen_proof_end.duration_since(gen_proof_start);
 
       ver_proof_start = Instant::now();
-      assert!(revelio_proof.verify());
+      assert!(proof.verify().is_ok());
       ver_proof_end = Instant::now();
       total_ver_proof_duration += ver_proof_end.duration_since(ver_proof_start);
+      revelio_proof = Some(proof);
     }
 
     let sim_end = Instant::now();
@@ -51,5 +120,20 @@ fn main() {
       total_gen_proof_duration.checked_div(num_iter).unwrap());
     println!("Average proof verification time = {:?}",
       total_ver_proof_duration.checked_div(num_iter).unwrap());
+
+    if let (Some(path), Some(proof)) = (opt.output_file, revelio_proof) {
+      if path.extension().is_some_and(|ext| ext == "stream") {
+        write_stream(&proof, BufWriter::new(File::create(&path).unwrap())).unwrap();
+        println!("Proof written to {}", path.display());
+        return;
+      }
+      let bytes = if path.extension().is_some_and(|ext| ext == "json") {
+        proof.to_json().into_bytes()
+      } else {
+        serialize(&proof)
+      };
+      fs::write(&path, bytes).unwrap();
+      println!("Proof written to {}", path.display());
+    }
 }
 
@@ -2,8 +2,13 @@ extern crate structopt;
 extern crate revelio;
 use structopt::StructOpt;
 
+use std::fs;
+use std::path::PathBuf;
 use std::time::{Instant, Duration};
+use rand::thread_rng;
+use revelio::ser::serialize;
 use revelio::exchange::SimpleGrinExchange;
+use revelio::transcript::NonceMode;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "simple", about = "Simple reserve proof generation simulator.")]
@@ -12,14 +17,24 @@ struct Opt {
   own_list_size: usize,
   #[structopt(short = "n", long = "numiter", default_value = "1")]
   num_iter: u32,
+  /// Write the last proof to this file, as JSON if it ends in .json
+  #[structopt(short = "w", long = "write", parse(from_os_str))]
+  output_file: Option<PathBuf>,
+  /// Derive the prover's random scalars deterministically from the secrets
+  #[structopt(short = "d", long = "deterministic")]
+  deterministic: bool,
 }
 
 fn main() {
     let opt = Opt::from_args();
 
     let num_iter = opt.num_iter;
-    let mut grin_exch = SimpleGrinExchange::new(opt.own_list_size);
-    let mut simple_proof;
+    let mut rng = thread_rng();
+    let mut grin_exch = SimpleGrinExchange::new(opt.own_list_size, &mut rng).unwrap();
+    if opt.deterministic {
+      grin_exch.set_nonce_mode(NonceMode::Deterministic);
+    }
+    let mut simple_proof = None;
     let mut gen_proof_start;
     let mut gen_proof_end;
     let mut ver_proof_start;
@@ -31,14 +46,15 @@ fn main() {
 
     for _i in 0..num_iter {
       gen_proof_start = Instant::now();
-      simple_proof = grin_exch.generate_proof();
+      let proof = grin_exch.generate_proof(&mut rng).unwrap();
       gen_proof_end = Instant::now();
       total_gen_proof_duration += gen_proof_end.duration_since(gen_proof_start);
 
       ver_proof_start = Instant::now();
-      assert!(simple_proof.verify());
+      assert!(proof.verify().is_ok());
       ver_proof_end = Instant::now();
       total_ver_proof_duration += ver_proof_end.duration_since(ver_proof_start);
+      simple_proof = Some(proof);
     }
 
     let sim_end = Instant::now();
@@ -49,5 +65,15 @@ fn main() {
       total_gen_proof_duration.checked_div(num_iter).unwrap());
     println!("Average proof verification time = {:?}",
       total_ver_proof_duration.checked_div(num_iter).unwrap());
+
+    if let (Some(path), Some(proof)) = (opt.output_file, simple_proof) {
+      let bytes = if path.extension().is_some_and(|ext| ext == "json") {
+        proof.to_json().into_bytes()
+      } else {
+        serialize(&proof)
+      };
+      fs::write(&path, bytes).unwrap();
+      println!("Proof written to {}", path.display());
+    }
 }
 
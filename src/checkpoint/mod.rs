@@ -0,0 +1,160 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use super::error::Error;
+use super::nizk::RevelioPoK;
+use super::ser::{self, Reader, Writeable, Writer, SCALAR_SIZE, POINT_SIZE, REVELIO_CHECKPOINT_TAG};
+use super::transcript::NonceMode;
+
+/// Number of output proofs created between two writes of a checkpoint
+pub const CHECKPOINT_INTERVAL: usize = 1024;
+
+/// Size of an output proof in a checkpoint, which always has its commitments
+const RECORD_SIZE: usize = 5 * SCALAR_SIZE + 3 * POINT_SIZE;
+
+/// Progress of a Revelio proof generation, kept in a file.
+///
+/// Binary layout: version, tag, statement digest, nonce, nonce mode, seed of
+/// the run, followed by the completed output proofs in index order. The
+/// output proofs are appended and synced to disk in batches, so after a crash
+/// the file holds every batch which was completed, possibly followed by part
+/// of a record, which is dropped. The seed makes the resumed run draw the
+/// same randomness as an uninterrupted one and is stored in plain text, so
+/// the file must be kept as secret as the exchange's keys. On Unix it is
+/// created with mode 0600, and a checkpoint with any other mode is refused.
+pub(crate) struct Checkpoint {
+  file: File,
+  pub(crate) run_seed: [u8; 32],
+  pub(crate) spks: Vec<RevelioPoK>,
+}
+
+impl Checkpoint {
+  /// Resumes the checkpoint at path, or creates it with the given seed if it
+  /// does not exist. A checkpoint of another statement, nonce or nonce mode
+  /// is an error rather than being overwritten, and so on Unix is one whose
+  /// mode is not 0600.
+  pub(crate) fn open(
+    path: &Path,
+    statement: &[u8; 32],
+    nonce: Option<&[u8]>,
+    nonce_mode: NonceMode,
+    run_seed: [u8; 32],
+  ) -> Result<Checkpoint, Error> {
+    let bytes = match fs::read(path) {
+      Ok(bytes) => bytes,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        return Checkpoint::create(path, statement, nonce, nonce_mode, run_seed);
+      },
+      Err(e) => return Err(e.into()),
+    };
+    #[cfg(unix)]
+    {
+      let mode = fs::metadata(path)?.permissions().mode() & 0o777;
+      if mode != 0o600 {
+        return Err(Error::CheckpointPermissions(mode));
+      }
+    }
+
+    let mut reader = Reader::new(&bytes);
+    reader.read_header(REVELIO_CHECKPOINT_TAG)?;
+    let found_statement: [u8; 32] = reader.read_fixed_bytes()?;
+    let found_nonce = match reader.read_u8()? {
+      0 => None,
+      1 => Some(reader.read_var_bytes()?),
+      flag => return Err(ser::Error::InvalidFlag(flag).into()),
+    };
+    let found_mode = reader.read_u8()?;
+    let run_seed = reader.read_fixed_bytes()?;
+    if found_statement != *statement ||
+       found_nonce.as_deref() != nonce ||
+       found_mode != nonce_mode_to_u8(nonce_mode) {
+      return Err(Error::CheckpointMismatch);
+    }
+
+    let mut spks = Vec::with_capacity(reader.remaining() / RECORD_SIZE);
+    while reader.remaining() >= RECORD_SIZE {
+      spks.push(RevelioPoK::read_with_commitments(&mut reader)?);
+    }
+
+    // A record cut short by a crash is dropped and created again
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len((bytes.len() - reader.remaining()) as u64)?;
+    file.seek(SeekFrom::End(0))?;
+
+    Ok(Checkpoint { file, run_seed, spks })
+  }
+
+  fn create(
+    path: &Path,
+    statement: &[u8; 32],
+    nonce: Option<&[u8]>,
+    nonce_mode: NonceMode,
+    run_seed: [u8; 32],
+  ) -> Result<Checkpoint, Error> {
+    let mut writer = Writer::new();
+    writer.write_header(REVELIO_CHECKPOINT_TAG);
+    writer.write_fixed_bytes(statement);
+    match nonce {
+      Some(nonce) => {
+        writer.write_u8(1);
+        writer.write_var_bytes(nonce);
+      },
+      None => writer.write_u8(0),
+    }
+    writer.write_u8(nonce_mode_to_u8(nonce_mode));
+    writer.write_fixed_bytes(&run_seed);
+
+    // The header is written under a temporary name and renamed into place,
+    // so a crash never leaves a checkpoint with a partial header. A file left
+    // under that name by an earlier crash may have any mode and is replaced.
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    match fs::remove_file(&tmp_path) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+      _ => {},
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(&writer.into_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    #[cfg(unix)]
+    {
+      let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+      };
+      File::open(dir)?.sync_all()?;
+    }
+    Ok(Checkpoint { file, run_seed, spks: Vec::new() })
+  }
+
+  /// Records the next completed output proofs and waits until they are on disk
+  pub(crate) fn append(&mut self, spks: Vec<RevelioPoK>) -> Result<(), Error> {
+    let mut writer = Writer::new();
+    for rspk in &spks {
+      rspk.write(&mut writer);
+      match rspk.commitments() {
+        Some(commitments) => commitments.write(&mut writer),
+        None => return Err(Error::InvalidOutputProof(self.spks.len())),
+      }
+    }
+    self.file.write_all(&writer.into_bytes())?;
+    self.file.sync_data()?;
+    self.spks.extend(spks);
+    Ok(())
+  }
+}
+
+fn nonce_mode_to_u8(mode: NonceMode) -> u8 {
+  match mode {
+    NonceMode::Random => 0,
+    NonceMode::Deterministic => 1,
+  }
+}
@@ -0,0 +1,29 @@
+use std::sync::OnceLock;
+use secp256k1zkp as secp;
+use secp::Secp256k1;
+
+static SECP_CONTEXT: OnceLock<Secp256k1> = OnceLock::new();
+
+/// Returns the secp256k1 context shared by the whole process.
+///
+/// Creating a context with commitment capabilities precomputes large tables
+/// and costs milliseconds, far more than the point operations it is used for.
+/// The shared context is created on first use and reused afterwards. Code
+/// which manages its own context can pass it to the functions in nizk
+/// instead.
+pub fn secp_context() -> &'static Secp256k1 {
+  SECP_CONTEXT.get_or_init(|| Secp256k1::with_caps(secp::ContextFlag::Commit))
+}
+
+#[cfg(test)]
+mod test {
+  use std::ptr;
+  use super::secp_context;
+
+  #[test]
+  fn context_is_created_once() {
+    assert!(ptr::eq(secp_context(), secp_context()));
+    let handle = std::thread::spawn(|| secp_context() as *const _ as usize);
+    assert!(handle.join().unwrap() == secp_context() as *const _ as usize);
+  }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::io;
+use secp256k1zkp as secp;
+
+use super::ser;
+
+/// Errors returned by the provers, verifiers and exchange constructors
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+  /// An operation in the secp256k1 library failed, e.g. a scalar was out of
+  /// range or a point sum was the point at infinity
+  Secp(secp::Error),
+  /// A proof could not be decoded
+  Ser(ser::Error),
+  /// An amount of zero was given where a positive amount is required
+  ZeroAmount,
+  /// A sum of amounts does not fit in a u64
+  AmountOverflow,
+  /// A list which must be non-empty is empty
+  EmptyList,
+  /// Lists which must have the same size differ in size
+  ListSizeMismatch { expected: usize, found: usize },
+  /// More own outputs were requested than fit in the anonymity list
+  TooManyOwnOutputs { own_list_size: usize, anon_list_size: usize },
+  /// An own output given to an exchange constructor is invalid
+  InvalidOwnOutput { index: usize, reason: &'static str },
+  /// The basepoints in a proof differ from the expected generators
+  NonCanonicalGenerators,
+  /// The output at this index is the point at infinity
+  IdentityOutput(usize),
+  /// The key image at this index is the point at infinity
+  IdentityKeyImage(usize),
+  /// The outputs at these indices are equal
+  DuplicateOutput { first: usize, second: usize },
+  /// The key images at these indices are equal
+  DuplicateKeyImage { first: usize, second: usize },
+  /// The proof of knowledge for the output at this index does not verify
+  InvalidOutputProof(usize),
+  /// The proof of the declared total amount does not verify
+  InvalidReserveProof,
+  /// The proof does not answer the auditor's challenge request
+  ChallengeMismatch(&'static str),
+  /// The statement digest announced at the start of a proof stream differs
+  /// from the digest of the records which followed
+  StatementMismatch,
+  /// A checkpoint file belongs to the generation of a different proof
+  CheckpointMismatch,
+  /// A checkpoint file can be accessed by others than its owner; the mode
+  /// found is given
+  CheckpointPermissions(u32),
+  /// Reading or writing a proof stream or checkpoint failed
+  Io(io::ErrorKind),
+  /// A thread pool with the requested thread count could not be built
+  ThreadPool(String),
+  /// Proofs which must come from one exchange carry different exchange ids
+  ExchangeMismatch { old: String, new: String },
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::Secp(ref e) => write!(f, "{}", e),
+      Error::Ser(ref e) => write!(f, "{}", e),
+      Error::ZeroAmount => write!(f, "amount must be positive"),
+      Error::AmountOverflow => write!(f, "sum of amounts overflows"),
+      Error::EmptyList => write!(f, "list is empty"),
+      Error::ListSizeMismatch { expected, found } =>
+        write!(f, "list has {} entries, expected {}", found, expected),
+      Error::TooManyOwnOutputs { own_list_size, anon_list_size } =>
+        write!(f, "{} own outputs do not fit in an anonymity list of size {}", own_list_size, anon_list_size),
+      Error::InvalidOwnOutput { index, reason } =>
+        write!(f, "own output {} is invalid: {}", index, reason),
+      Error::NonCanonicalGenerators => write!(f, "proof uses non-canonical generators"),
+      Error::IdentityOutput(i) => write!(f, "output {} is the point at infinity", i),
+      Error::IdentityKeyImage(i) => write!(f, "key image {} is the point at infinity", i),
+      Error::DuplicateOutput { first, second } =>
+        write!(f, "outputs {} and {} are equal", first, second),
+      Error::DuplicateKeyImage { first, second } =>
+        write!(f, "key images {} and {} are equal", first, second),
+      Error::InvalidOutputProof(i) => write!(f, "proof for output {} is invalid", i),
+      Error::InvalidReserveProof => write!(f, "proof of the total amount is invalid"),
+      Error::ChallengeMismatch(reason) => write!(f, "proof does not answer the challenge: {}", reason),
+      Error::StatementMismatch => write!(f, "records do not match the statement digest of the stream"),
+      Error::CheckpointMismatch => write!(f, "checkpoint was made for a different proof"),
+      Error::CheckpointPermissions(mode) =>
+        write!(f, "checkpoint has mode {:o}, expected 600", mode),
+      Error::Io(kind) => write!(f, "i/o error: {:?}", kind),
+      Error::ThreadPool(ref e) => write!(f, "cannot build thread pool: {}", e),
+      Error::ExchangeMismatch { ref old, ref new } =>
+        write!(f, "proofs are from different exchanges {:?} and {:?}", old, new),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<secp::Error> for Error {
+  fn from(e: secp::Error) -> Error {
+    Error::Secp(e)
+  }
+}
+
+impl From<ser::Error> for Error {
+  fn from(e: ser::Error) -> Error {
+    Error::Ser(e)
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+      Error::Ser(ser::Error::UnexpectedEof)
+    } else {
+      Error::Io(e.kind())
+    }
+  }
+}
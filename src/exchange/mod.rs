@@ -1,12 +1,24 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
 use digest::Digest;
-use sha2::Sha256;
-use rand::{thread_rng, Rng};
-use rand::seq::SliceRandom;
+use sha2::{Sha256, Sha512};
+use rand::{Rng, RngCore, CryptoRng, SeedableRng};
+use rand::rngs::StdRng;
 use secp256k1zkp as secp;
 use secp::Secp256k1;
 use secp::key::{SecretKey, PublicKey, ZERO_KEY};
 
-use super::nizk::{RevelioPoK, RepresentationPoK};
+use super::checkpoint::{Checkpoint, CHECKPOINT_INTERVAL};
+use super::context::secp_context;
+use super::error::Error;
+use super::nizk::{RevelioPoK, DiscreteLogPoK, PoKEncoding, MINUS_ONE_KEY};
+use super::parallel;
+use super::transcript::{NonceMode, reduce_wide, WIDE_SIZE};
+use super::json::{self, SimpleProofJson, RevelioProofJson, RevelioPoKJson, SIMPLE_PROOF_TYPE, REVELIO_PROOF_TYPE};
+use super::json::{point_to_hex, point_from_hex, point_list_to_hex, point_list_from_hex};
+use super::ser::{self, Readable, Reader, Writeable, Writer, SCALAR_SIZE, POINT_SIZE, PROTOCOL_VERSION};
+use super::ser::{SIMPLE_PROOF_TAG, REVELIO_PROOF_TAG};
 
 const MAX_AMOUNT_PER_OUTPUT: u64 = 1000;
 
@@ -34,17 +46,350 @@ pub const GENERATOR_H : [u8;65] = [
     0xc3, 0x0c, 0x23, 0x13, 0xf3, 0xa3, 0x89, 0x04
 ];
 
-pub const GENERATOR_J_COMPR : [u8;33] = [
+/// Domain separation tag used when hashing H to obtain G'
+pub const KEYIMAGE_GEN_DST: &[u8] = b"Revelio/keyimage_generator";
+
+/// Domain separation tag of the statement digest of a SimpleProof
+pub const SIMPLE_STATEMENT_DST: &[u8] = b"Revelio/simple_statement";
+
+/// Domain separation tag of the statement digest of a RevelioProof
+pub const REVELIO_STATEMENT_DST: &[u8] = b"Revelio/revelio_statement";
+
+/// The key image generator G', i.e. the output of derive_keyimage_generator
+pub const GENERATOR_GPRIME_COMPR : [u8;33] = [
     0x02,
-    0xb8, 0x60, 0xf5, 0x67, 0x95, 0xfc, 0x03, 0xf3,
-    0xc2, 0x16, 0x85, 0x38, 0x3d, 0x1b, 0x5a, 0x2f,
-    0x29, 0x54, 0xf4, 0x9b, 0x7e, 0x39, 0x8b, 0x8d,
-    0x2a, 0x01, 0x93, 0x93, 0x36, 0x21, 0x15, 0x5f
+    0x30, 0x48, 0x5b, 0xf1, 0x22, 0x19, 0xa7, 0xf7,
+    0xd2, 0x0a, 0x4c, 0x50, 0xb2, 0xfd, 0xad, 0x69,
+    0x35, 0x90, 0x02, 0x43, 0xaf, 0xfe, 0x05, 0xad,
+    0xf9, 0x7f, 0x63, 0xdd, 0xfb, 0x5f, 0xba, 0xdb
 ];
 
+/// Derives G' from H by hashing to the curve with try-and-increment.
+///
+/// For counter = 0, 1, 2, ... the x-coordinate candidate
+/// SHA256(KEYIMAGE_GEN_DST || compressed(H) || counter as 4 big-endian bytes)
+/// is computed and the first candidate that is the x-coordinate of a curve
+/// point is taken, choosing the point with even y. Nobody knows the discrete
+/// logarithm of the result with respect to G or H.
+pub fn derive_keyimage_generator() -> PublicKey {
+  let secp_inst = secp_context();
+  let value_gen = PublicKey::from_slice(secp_inst, &GENERATOR_H).unwrap();
+
+  let mut counter = 0u32;
+  loop {
+    let mut hasher = Sha256::new();
+    hasher.input(KEYIMAGE_GEN_DST);                                // Hash tag
+    hasher.input(value_gen.serialize_vec(secp_inst, true));        // Hash H
+    hasher.input(counter.to_be_bytes());                           // Hash counter
+
+    let mut candidate = vec![0x02u8];
+    candidate.extend_from_slice(&hasher.result());
+    if let Ok(point) = PublicKey::from_slice(secp_inst, &candidate) {
+      return point;
+    }
+    counter += 1;
+  }
+}
+
+/// Generators which a verifier expects every proof to be created with
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Generators {
+  pub blinding_gen: PublicKey, // G
+  pub value_gen: PublicKey,    // H
+  pub keyimage_gen: PublicKey, // G'
+}
+
+impl Default for Generators {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Generators {
+  /// The canonical generators used by Grin and Revelio
+  pub fn new() -> Generators {
+    let secp_inst = secp_context();
+    Generators {
+      blinding_gen: PublicKey::from_slice(secp_inst, &GENERATOR_G).unwrap(),
+      value_gen: PublicKey::from_slice(secp_inst, &GENERATOR_H).unwrap(),
+      keyimage_gen: PublicKey::from_slice(secp_inst, &GENERATOR_GPRIME_COMPR).unwrap(),
+    }
+  }
+}
+
+/// An output owned by an exchange as a (commitment, blinding factor, amount) triple
+pub type OwnOutput = (PublicKey, SecretKey, u64);
+
+/// Size of a DecoySeed
+pub const DECOY_SEED_SIZE: usize = 32;
+
+/// Domain separation tag of the derivation of decoy keys from a DecoySeed
+pub const DECOY_KEY_DST: &[u8] = b"Revelio/decoy_key";
+
+/// Long-term secret of an exchange from which the key of every decoy output
+/// is derived. A decoy key depends only on the seed and the output, so the
+/// seed must be stored to reproduce the key images of a proof. Revealing the
+/// seed to an auditor lets them recompute every decoy key image and so learn
+/// which outputs are the exchange's own.
+#[derive(Clone, PartialEq)]
+pub struct DecoySeed([u8; DECOY_SEED_SIZE]);
+
+impl DecoySeed {
+  pub fn random(rng: &mut (impl RngCore + CryptoRng)) -> DecoySeed {
+    let mut seed = [0u8; DECOY_SEED_SIZE];
+    rng.fill_bytes(&mut seed);
+    DecoySeed(seed)
+  }
+
+  pub fn from_bytes(bytes: [u8; DECOY_SEED_SIZE]) -> DecoySeed {
+    DecoySeed(bytes)
+  }
+
+  pub fn to_bytes(&self) -> [u8; DECOY_SEED_SIZE] {
+    self.0
+  }
+
+  pub fn to_hex(&self) -> String {
+    hex::encode(self.0)
+  }
+
+  pub fn from_hex(s: &str) -> Result<DecoySeed, json::Error> {
+    let bytes = hex::decode(s.trim())?;
+    if bytes.len() != DECOY_SEED_SIZE {
+      return Err(json::Error::Ser(ser::Error::InvalidLength(bytes.len() as u64)));
+    }
+    let mut seed = [0u8; DECOY_SEED_SIZE];
+    seed.copy_from_slice(&bytes);
+    Ok(DecoySeed(seed))
+  }
+
+  /// Derives the decoy key of an output as
+  /// SHA512(DECOY_KEY_DST || seed || C_i) reduced modulo the group order
+  pub fn decoy_key(&self, output: &PublicKey) -> SecretKey {
+    let mut hasher = Sha512::new();
+    hasher.input(DECOY_KEY_DST);
+    hasher.input(self.0);
+    hasher.input(output.serialize_vec(secp_context(), true));
+    let mut wide = [0u8; WIDE_SIZE];
+    wide.copy_from_slice(&hasher.result());
+    reduce_wide(&wide)
+  }
+
+  /// The key image k_i*G' which the exchange publishes for a decoy output
+  pub fn decoy_keyimage(&self, output: &PublicKey) -> Result<PublicKey, Error> {
+    RevelioGrinExchange::create_keyimage(0, self.decoy_key(output))
+  }
+}
+
+/// Checks that the i-th own output opens its commitment
+fn check_own_output(
+  secp_inst: &Secp256k1,
+  i: usize,
+  commitment: &PublicKey,
+  blinding: &SecretKey,
+  amount: u64,
+) -> Result<(), Error> {
+  if amount == 0u64 {
+    return Err(Error::InvalidOwnOutput { index: i, reason: "zero amount" });
+  }
+  if *blinding == ZERO_KEY {
+    return Err(Error::InvalidOwnOutput { index: i, reason: "zero blinding factor" });
+  }
+
+  let opened = Secp256k1::commit(secp_inst, amount, blinding.clone())
+                  .and_then(|c| c.to_pubkey(secp_inst));
+  match opened {
+    Ok(ref c) if c == commitment => Ok(()),
+    _ => Err(Error::InvalidOwnOutput { index: i, reason: "does not open its commitment" }),
+  }
+}
+
+/// Generates an own output with a random blinding factor and amount
+fn random_own_output(
+  secp_inst: &Secp256k1,
+  rng: &mut (impl RngCore + CryptoRng),
+) -> Result<OwnOutput, Error> {
+  let blinding = SecretKey::new(secp_inst, rng);
+  let amount = rng.gen_range(1, MAX_AMOUNT_PER_OUTPUT);
+  let commitment = Secp256k1::commit(secp_inst, amount, blinding.clone())?
+                      .to_pubkey(secp_inst)?;
+  Ok((commitment, blinding, amount))
+}
+
+/// Draws alist_size - olist_size random decoy outputs and olist_size random
+/// own outputs
+fn random_outputs(
+  alist_size: usize,
+  olist_size: usize,
+  rng: &mut (impl RngCore + CryptoRng),
+) -> Result<(Vec<PublicKey>, Vec<OwnOutput>), Error> {
+
+  if olist_size > alist_size {
+    return Err(Error::TooManyOwnOutputs { own_list_size: olist_size, anon_list_size: alist_size });
+  }
+  let secp_inst = secp_context();
+  let mut decoy_list = Vec::with_capacity(alist_size - olist_size);
+  let mut own_outputs = Vec::with_capacity(olist_size);
+
+  for _i in 0..alist_size - olist_size {
+    let temp_sk = SecretKey::new(secp_inst, rng);
+    decoy_list.push(PublicKey::from_secret_key(secp_inst, &temp_sk)?);
+  }
+
+  for _i in 0..olist_size {
+    own_outputs.push(random_own_output(secp_inst, rng)?);
+  }
+  Ok((decoy_list, own_outputs))
+}
+
+/// Calculates point - amount*H
+pub(crate) fn subtract_amount(
+  secp_inst: &Secp256k1,
+  point: PublicKey,
+  amount: u64,
+  value_gen: PublicKey,
+) -> Result<PublicKey, Error> {
+  if amount == 0u64 {
+    Ok(point)
+  } else {
+    let mut minus_amount = RevelioPoK::amount_to_key(secp_inst, amount)?;
+    minus_amount.mul_assign(secp_inst, &MINUS_ONE_KEY)?;
+    let mut minus_amount_h = value_gen;
+    minus_amount_h.mul_assign(secp_inst, &minus_amount)?;
+
+    Ok(PublicKey::from_combination(secp_inst, vec![&point, &minus_amount_h])?)
+  }
+}
+
+/// Hashes a domain separation tag followed by an encoded statement
+fn hash_statement(dst: &[u8], writer: Writer) -> [u8; 32] {
+  let mut hasher = Sha256::new();
+  hasher.input(dst);
+  hasher.input(writer.into_bytes());
+
+  let mut digest = [0u8; 32];
+  digest.copy_from_slice(&hasher.result());
+  digest
+}
+
+/// Computes the statement digest of a RevelioProof one output at a time, so
+/// that it can be checked without holding the anonymity list in memory
+pub(crate) struct RevelioStatementHasher {
+  hasher: Sha256,
+}
+
+impl RevelioStatementHasher {
+  pub(crate) fn new(
+    blinding_gen: &PublicKey,
+    value_gen: &PublicKey,
+    keyimage_gen: &PublicKey,
+    context: &ProofContext,
+    total_amount: u64,
+    anon_list_size: u64,
+  ) -> RevelioStatementHasher {
+    let mut writer = Writer::new();
+    writer.write_point(blinding_gen);
+    writer.write_point(value_gen);
+    writer.write_point(keyimage_gen);
+    context.write(&mut writer);
+    writer.write_u64(total_amount);
+    writer.write_u64(anon_list_size);
+
+    let mut hasher = Sha256::new();
+    hasher.input(REVELIO_STATEMENT_DST);
+    hasher.input(writer.into_bytes());
+    RevelioStatementHasher { hasher }
+  }
+
+  pub(crate) fn add_output(&mut self, output: &PublicKey, keyimage: &PublicKey) {
+    let mut writer = Writer::new();
+    writer.write_point(output);
+    writer.write_point(keyimage);
+    self.hasher.input(writer.into_bytes());
+  }
+
+  pub(crate) fn finish(self) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&self.hasher.result());
+    digest
+  }
+}
+
+/// Returns true if the point is the point at infinity, which the secp256k1
+/// library represents as a zeroed PublicKey
+pub(crate) fn is_identity(point: &PublicKey) -> bool {
+  !point.is_valid()
+}
+
+/// Returns the index of the first point at infinity in the list
+fn find_identity(list: &[PublicKey]) -> Option<usize> {
+  list.iter().position(is_identity)
+}
+
+/// Verifies the proofs of the first spks.len() outputs. With `batch` they are
+/// all checked at once, and one by one only to locate a failure.
+fn verify_output_proofs(
+  anon_list: &[PublicKey],
+  keyimage_list: &[PublicKey],
+  spks: &[RevelioPoK],
+  gens: &Generators,
+  statement: &[u8; 32],
+  nonce: Option<&[u8]>,
+  batch: bool,
+) -> Result<(), Error> {
+  let secp_inst = secp_context();
+  let batch_valid = batch && !spks.is_empty() &&
+                    RevelioPoK::batch_verify_spk(
+                      secp_inst,
+                      &anon_list[..spks.len()],
+                      &keyimage_list[..spks.len()],
+                      spks,
+                      &gens.blinding_gen,
+                      &gens.value_gen,
+                      &gens.keyimage_gen,
+                      statement,
+                      nonce,
+                    )?;
+
+  if !batch_valid {
+    let results = parallel::map_indices(spks.len(), |i| {
+      RevelioPoK::verify_spk(
+        secp_inst,
+        &anon_list[i],
+        &keyimage_list[i],
+        &gens.blinding_gen,
+        &gens.value_gen,
+        &gens.keyimage_gen,
+        statement,
+        nonce,
+        &spks[i],
+      )
+    });
+    for (i, result) in results.into_iter().enumerate() {
+      if !result? {
+        return Err(Error::InvalidOutputProof(i));
+      } // end if
+    } // end for
+  } // end if
+  Ok(())
+}
+
+/// Returns the indices of the first pair of equal points in the list
+fn find_duplicate(list: &[PublicKey]) -> Option<(usize, usize)> {
+  let mut seen = HashMap::with_capacity(list.len());
+  for (i, point) in list.iter().enumerate() {
+    if let Some(j) = seen.insert(*point, i) {
+      return Some((j, i));
+    }
+  }
+  None
+}
+
+#[derive(Clone)]
 pub struct SimpleProof {
   pub own_list: Vec<PublicKey>,
-  pub rep_spk: RepresentationPoK,
+  pub total_amount: u64,
+  pub reserve_spk: DiscreteLogPoK,
   blinding_basepoint: PublicKey,
   value_basepoint: PublicKey,
 }
@@ -52,31 +397,152 @@ pub struct SimpleProof {
 impl SimpleProof {
   pub fn new(own_list_size: usize) -> SimpleProof {
     let zeropk = PublicKey::new();
-    let empty_spk = RepresentationPoK::new();
+    let empty_spk = DiscreteLogPoK::new();
     SimpleProof {
       own_list: vec![zeropk; own_list_size],
-      rep_spk: empty_spk,
+      total_amount: 0,
+      reserve_spk: empty_spk,
       blinding_basepoint: zeropk,
       value_basepoint: zeropk,
     }
   }
 
-  pub fn verify(&self) -> bool {
-    assert!(self.own_list.len() != 0);
+  /// Verifies the proof against the canonical generators
+  pub fn verify(&self) -> Result<(), Error> {
+    self.verify_with_generators(&Generators::new())
+  }
+
+  /// Verifies the proof, rejecting it if its basepoints differ from `gens`
+  pub fn verify_with_generators(&self, gens: &Generators) -> Result<(), Error> {
+    if self.own_list.is_empty() {
+      return Err(Error::EmptyList);
+    }
 
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+    if self.blinding_basepoint != gens.blinding_gen || self.value_basepoint != gens.value_gen {
+      return Err(Error::NonCanonicalGenerators);
+    }
+
+    if let Some(i) = find_identity(&self.own_list) {
+      return Err(Error::IdentityOutput(i));
+    }
+    if let Some((first, second)) = find_duplicate(&self.own_list) {
+      return Err(Error::DuplicateOutput { first, second });
+    }
+
+    // Check that sum(C_i) - total_amount*H = x*G for an x known to the exchange
+    let secp_inst = secp_context();
+    let reserve_point = self.reserve_point(gens)?;
+    if !DiscreteLogPoK::verify_dlog_spk(
+      secp_inst,
+      &reserve_point,
+      &gens.blinding_gen,
+      &self.statement_digest(),
+      None,
+      &self.reserve_spk,
+    )? {
+      return Err(Error::InvalidReserveProof);
+    }
+
+    Ok(())
+  }
+
+  /// Hashes the generators, the declared total and the own list. The
+  /// challenge of the reserve proof commits to this digest.
+  pub fn statement_digest(&self) -> [u8; 32] {
+    let mut writer = Writer::new();
+    writer.write_point(&self.blinding_basepoint);
+    writer.write_point(&self.value_basepoint);
+    writer.write_u64(self.total_amount);
+    writer.write_point_list(&self.own_list);
+    hash_statement(SIMPLE_STATEMENT_DST, writer)
+  }
+
+  /// Calculates sum(C_i) - total_amount*H which should be a multiple of G
+  fn reserve_point(&self, gens: &Generators) -> Result<PublicKey, Error> {
+    let secp_inst = secp_context();
 
     let mut sum_outputs = self.own_list[0];
     for output in &self.own_list[1..] {
-      sum_outputs = PublicKey::from_combination(&secp_inst, vec![&sum_outputs, &output]).unwrap(); // sum_outputs += output
+      sum_outputs = PublicKey::from_combination(secp_inst, vec![&sum_outputs, output])?;  // sum_outputs += output
     }
 
-    RepresentationPoK::verify_representation_spk(
-      &sum_outputs,
-      &self.blinding_basepoint,
-      &self.value_basepoint,
-      &self.rep_spk,
-    )
+    subtract_amount(secp_inst, sum_outputs, self.total_amount, gens.value_gen)
+  }
+}
+
+/// Binary layout: version, tag, G, H, total_amount, reserve_spk, own_list
+impl Writeable for SimpleProof {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_header(SIMPLE_PROOF_TAG);
+    writer.write_point(&self.blinding_basepoint);
+    writer.write_point(&self.value_basepoint);
+    writer.write_u64(self.total_amount);
+    self.reserve_spk.write(writer);
+    writer.write_point_list(&self.own_list);
+  }
+}
+
+impl Readable for SimpleProof {
+  fn read(reader: &mut Reader) -> Result<SimpleProof, ser::Error> {
+    reader.read_header(SIMPLE_PROOF_TAG)?;
+    let blinding_basepoint = reader.read_point()?;
+    let value_basepoint = reader.read_point()?;
+    let total_amount = reader.read_u64()?;
+    let reserve_spk = DiscreteLogPoK::read(reader)?;
+    let own_list = reader.read_point_list()?;
+
+    Ok(SimpleProof {
+      own_list,
+      total_amount,
+      reserve_spk,
+      blinding_basepoint,
+      value_basepoint,
+    })
+  }
+}
+
+impl From<&SimpleProof> for SimpleProofJson {
+  fn from(proof: &SimpleProof) -> SimpleProofJson {
+    SimpleProofJson {
+      version: PROTOCOL_VERSION,
+      proof_type: SIMPLE_PROOF_TYPE.to_string(),
+      own_list_size: proof.own_list.len(),
+      blinding_basepoint: point_to_hex(&proof.blinding_basepoint),
+      value_basepoint: point_to_hex(&proof.value_basepoint),
+      total_amount: proof.total_amount,
+      reserve_spk: (&proof.reserve_spk).into(),
+      own_list: point_list_to_hex(&proof.own_list),
+    }
+  }
+}
+
+impl TryFrom<&SimpleProofJson> for SimpleProof {
+  type Error = json::Error;
+
+  fn try_from(proof: &SimpleProofJson) -> Result<SimpleProof, json::Error> {
+    json::check_header(proof.version, &proof.proof_type, SIMPLE_PROOF_TYPE)?;
+    json::check_list_size("own_list", proof.own_list_size, proof.own_list.len())?;
+
+    Ok(SimpleProof {
+      own_list: point_list_from_hex(&proof.own_list)?,
+      total_amount: proof.total_amount,
+      reserve_spk: DiscreteLogPoK::try_from(&proof.reserve_spk)?,
+      blinding_basepoint: point_from_hex(&proof.blinding_basepoint)?,
+      value_basepoint: point_from_hex(&proof.value_basepoint)?,
+    })
+  }
+}
+
+impl SimpleProof {
+  /// Encodes the proof as pretty-printed JSON with hex points and scalars
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(&SimpleProofJson::from(self)).unwrap()
+  }
+
+  /// Decodes a proof from its JSON encoding
+  pub fn from_json(s: &str) -> Result<SimpleProof, json::Error> {
+    let proof: SimpleProofJson = serde_json::from_str(s)?;
+    SimpleProof::try_from(&proof)
   }
 }
 
@@ -85,73 +551,166 @@ pub struct SimpleGrinExchange {
   simple_proof: SimpleProof,
   own_keys: Vec<SecretKey>,
   own_amounts: Vec<u64>,
+  nonce_mode: NonceMode,
 }
 
 impl SimpleGrinExchange {
-  pub fn new(olist_size: usize) -> SimpleGrinExchange  {
+  /// Creates an exchange owning olist_size random outputs, for simulations
+  pub fn new(
+    olist_size: usize,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<SimpleGrinExchange, Error>  {
 
-    let mut simproof = SimpleProof::new(olist_size);
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-    let mut okeys = vec![ZERO_KEY; olist_size];
-    let mut amounts = vec![0u64; olist_size];
+    let secp_inst = secp_context();
+    let mut own_outputs = Vec::with_capacity(olist_size);
+
+    for _i in 0..olist_size {
+      own_outputs.push(random_own_output(secp_inst, rng)?);
+    }
+
+    SimpleGrinExchange::from_outputs(own_outputs)
+  }
+
+  /// Creates an exchange from its own outputs given as
+  /// (commitment, blinding factor, amount) triples
+  pub fn from_outputs(own_outputs: Vec<OwnOutput>) -> Result<SimpleGrinExchange, Error> {
 
-    let mut rng = thread_rng();
+    if own_outputs.is_empty() {
+      return Err(Error::EmptyList);
+    }
+
+    let olist_size = own_outputs.len();
+    let mut simproof = SimpleProof::new(olist_size);
+    let secp_inst = secp_context();
+    let mut okeys = Vec::with_capacity(olist_size);
+    let mut amounts = Vec::with_capacity(olist_size);
 
-    for i in 0..olist_size {
-      okeys[i] = SecretKey::new(&secp_inst, &mut rng);
-      amounts[i] = rng.gen_range(1, MAX_AMOUNT_PER_OUTPUT);
-      simproof.own_list[i] = Secp256k1::commit(&secp_inst, amounts[i], okeys[i]).unwrap()
-                                .to_pubkey(&secp_inst).unwrap();
+    for (i, (commitment, blinding, amount)) in own_outputs.into_iter().enumerate() {
+      check_own_output(secp_inst, i, &commitment, &blinding, amount)?;
+      if simproof.own_list[..i].contains(&commitment) {
+        return Err(Error::InvalidOwnOutput { index: i, reason: "appears more than once" });
+      }
+      simproof.own_list[i] = commitment;
+      okeys.push(blinding);
+      amounts.push(amount);
     }
 
-    simproof.blinding_basepoint = PublicKey::from_slice(&secp_inst, &GENERATOR_G).unwrap();
-    simproof.value_basepoint = PublicKey::from_slice(&secp_inst, &GENERATOR_H).unwrap();
+    let gens = Generators::new();
+    simproof.blinding_basepoint = gens.blinding_gen;
+    simproof.value_basepoint = gens.value_gen;
 
-    SimpleGrinExchange  {
+    Ok(SimpleGrinExchange  {
       own_list_size: olist_size,
       simple_proof: simproof,
       own_keys: okeys,
       own_amounts: amounts,
-    }
+      nonce_mode: NonceMode::default(),
+    })
+  }
+
+  /// Sets how the random scalars of subsequent proofs are derived
+  pub fn set_nonce_mode(&mut self, mode: NonceMode) {
+    self.nonce_mode = mode;
   }
 
-  pub fn generate_proof(&mut self) -> SimpleProof {
+  pub fn generate_proof(&mut self, rng: &mut (impl RngCore + CryptoRng)) -> Result<SimpleProof, Error> {
 
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-    let mut sum_outputs = self.simple_proof.own_list[0];
-    let mut total_blinding_factor = self.own_keys[0];
+    let secp_inst = secp_context();
+    let mut total_blinding_factor = self.own_keys[0].clone();
     let mut sum_amount = self.own_amounts[0];
 
     for i in 1..self.own_list_size {
-      sum_outputs = PublicKey::from_combination(&secp_inst, vec![&sum_outputs, &self.simple_proof.own_list[i]]).unwrap(); // sum_outputs += output
-      total_blinding_factor.add_assign(&secp_inst, &self.own_keys[i]).unwrap();
-      sum_amount += &self.own_amounts[i];
+      total_blinding_factor.add_assign(secp_inst, &self.own_keys[i])?;
+      sum_amount = sum_amount.checked_add(self.own_amounts[i]).ok_or(Error::AmountOverflow)?;
     }
 
-    self.simple_proof.rep_spk = RepresentationPoK::create_representation_spk(
-                                  sum_outputs,
-                                  total_blinding_factor,
-                                  sum_amount,
-                                  self.simple_proof.blinding_basepoint, // G
-                                  self.simple_proof.value_basepoint,    // H
-                                );
+    // Proving knowledge of x = sum of own keys in sum(C_i) - sum_amount*H = x*G
+    self.simple_proof.total_amount = sum_amount;
+    let reserve_point = self.simple_proof.reserve_point(&Generators::new())?;
+    self.simple_proof.reserve_spk = DiscreteLogPoK::create_dlog_spk(
+                                      secp_inst,
+                                      reserve_point,
+                                      total_blinding_factor,
+                                      self.simple_proof.blinding_basepoint, // G
+                                      &self.simple_proof.statement_digest(),
+                                      None,
+                                      self.nonce_mode,
+                                      rng,
+                                    )?;
 
-    SimpleProof {
-      own_list: self.simple_proof.own_list.clone(),
-      rep_spk: self.simple_proof.rep_spk.clone(),
-      blinding_basepoint: self.simple_proof.blinding_basepoint,
-      value_basepoint: self.simple_proof.value_basepoint,
-    }
+    Ok(self.simple_proof.clone())
   } // end generate_proof
 } // end SimpleGrinExchange implementation
 
+/// The circumstances a proof is made in. They are part of the statement so a
+/// proof cannot be passed off as made by another exchange or at another height.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ProofContext {
+  pub exchange_id: String,
+  pub block_height: u64,
+}
+
+impl Writeable for ProofContext {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_var_bytes(self.exchange_id.as_bytes());
+    writer.write_u64(self.block_height);
+  }
+}
+
+impl Readable for ProofContext {
+  fn read(reader: &mut Reader) -> Result<ProofContext, ser::Error> {
+    let exchange_id = String::from_utf8(reader.read_var_bytes()?)
+                        .map_err(|_| ser::Error::InvalidString)?;
+    let block_height = reader.read_u64()?;
+    Ok(ProofContext { exchange_id, block_height })
+  }
+}
+
+/// Size of the nonce in a ChallengeRequest
+pub const CHALLENGE_NONCE_SIZE: usize = 32;
+
+/// A request from an auditor for a fresh proof. The random nonce flows into
+/// every challenge of the proof, so a proof made before the request or shown
+/// by someone other than the exchange does not answer it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChallengeRequest {
+  pub context: ProofContext,
+  pub nonce: Vec<u8>,
+}
+
+impl ChallengeRequest {
+  /// Creates a request with a random nonce for a proof in the given context
+  pub fn new(context: ProofContext, rng: &mut (impl RngCore + CryptoRng)) -> ChallengeRequest {
+    let mut nonce = vec![0u8; CHALLENGE_NONCE_SIZE];
+    rng.fill_bytes(&mut nonce);
+    ChallengeRequest { context, nonce }
+  }
+
+  /// Checks that the proof answers this request and verifies it
+  pub fn verify_response(&self, proof: &RevelioProof) -> Result<(), Error> {
+    if proof.context != self.context {
+      return Err(Error::ChallengeMismatch("proof context differs from the request"));
+    }
+    if proof.nonce.as_ref() != Some(&self.nonce) {
+      return Err(Error::ChallengeMismatch("proof nonce differs from the request"));
+    }
+    proof.verify()
+  }
+}
+
+#[derive(Clone)]
 pub struct RevelioProof {
   pub anon_list: Vec<PublicKey>,
   pub keyimage_list: Vec<PublicKey>,
   pub spk_list: Vec<RevelioPoK>,
-  blinding_basepoint: PublicKey,
-  value_basepoint: PublicKey,
-  keyimage_basepoint: PublicKey,
+  pub context: ProofContext,
+  pub nonce: Option<Vec<u8>>,
+  pub total_amount: u64,
+  pub reserve_spk: DiscreteLogPoK,
+  encoding: PoKEncoding,
+  pub(crate) blinding_basepoint: PublicKey,
+  pub(crate) value_basepoint: PublicKey,
+  pub(crate) keyimage_basepoint: PublicKey,
 }
 
 impl RevelioProof {
@@ -162,31 +721,298 @@ impl RevelioProof {
       anon_list: vec![zeropk; anon_list_size],
       keyimage_list: vec![zeropk; anon_list_size],
       spk_list: vec![empty_spk; anon_list_size],
+      context: ProofContext::default(),
+      nonce: None,
+      total_amount: 0,
+      reserve_spk: DiscreteLogPoK::new(),
+      encoding: PoKEncoding::default(),
       blinding_basepoint: zeropk,
       value_basepoint: zeropk,
       keyimage_basepoint: zeropk,
     }
   }
 
-  pub fn verify(&self) -> bool {
-    assert!(self.anon_list.len() == self.keyimage_list.len());
-    assert!(self.anon_list.len() == self.spk_list.len());
-    assert!(self.anon_list.len() != 0);
+  /// How the output proofs are encoded
+  pub fn encoding(&self) -> PoKEncoding {
+    self.encoding
+  }
 
-    for i in 0..self.anon_list.len() {
-      if RevelioPoK::verify_spk(
-        &self.anon_list[i],
-        &self.keyimage_list[i],
-        &self.blinding_basepoint,
-        &self.value_basepoint,
-        &self.keyimage_basepoint,
-        &self.spk_list[i],
-      ) == false {
-        return false;
-      } // end if
-    } // end for
+  /// Changes how the output proofs are encoded. Switching to the commitment
+  /// form recomputes the commitments of output proofs which lack them.
+  pub fn set_encoding(&mut self, encoding: PoKEncoding) -> Result<(), Error> {
+    if encoding == PoKEncoding::Commitment {
+      let secp_inst = secp_context();
+      for i in 0..self.spk_list.len() {
+        self.spk_list[i] = self.spk_list[i].with_commitments(
+                             secp_inst,
+                             &self.anon_list[i],
+                             &self.keyimage_list[i],
+                             &self.value_basepoint,
+                             &self.keyimage_basepoint,
+                           )?;
+      }
+    }
+    self.encoding = encoding;
+    Ok(())
+  }
+
+  /// The encoding the proof is written in, which falls back to the challenge
+  /// form if some output proof lacks its commitments
+  pub(crate) fn written_encoding(&self) -> PoKEncoding {
+    if self.spk_list.iter().all(|rspk| rspk.commitments().is_some()) {
+      self.encoding
+    } else {
+      PoKEncoding::Challenge
+    }
+  }
+
+  /// Verifies the proof against the canonical generators
+  pub fn verify(&self) -> Result<(), Error> {
+    self.verify_with_generators(&Generators::new())
+  }
+
+  /// Verifies the proof, rejecting it if its basepoints differ from `gens`
+  pub fn verify_with_generators(&self, gens: &Generators) -> Result<(), Error> {
+    if self.anon_list.is_empty() {
+      return Err(Error::EmptyList);
+    }
+    if self.keyimage_list.len() != self.anon_list.len() {
+      return Err(Error::ListSizeMismatch { expected: self.anon_list.len(), found: self.keyimage_list.len() });
+    }
+    if self.spk_list.len() != self.anon_list.len() {
+      return Err(Error::ListSizeMismatch { expected: self.anon_list.len(), found: self.spk_list.len() });
+    }
+
+    if self.blinding_basepoint != gens.blinding_gen ||
+       self.value_basepoint != gens.value_gen ||
+       self.keyimage_basepoint != gens.keyimage_gen {
+      return Err(Error::NonCanonicalGenerators);
+    }
+
+    // A repeated output or key image would count the same coins twice
+    if let Some(i) = find_identity(&self.anon_list) {
+      return Err(Error::IdentityOutput(i));
+    }
+    if let Some(i) = find_identity(&self.keyimage_list) {
+      return Err(Error::IdentityKeyImage(i));
+    }
+    if let Some((first, second)) = find_duplicate(&self.anon_list) {
+      return Err(Error::DuplicateOutput { first, second });
+    }
+    if let Some((first, second)) = find_duplicate(&self.keyimage_list) {
+      return Err(Error::DuplicateKeyImage { first, second });
+    }
+
+    let secp_inst = secp_context();
+    let statement = self.statement_digest();
+    verify_output_proofs(
+      &self.anon_list,
+      &self.keyimage_list,
+      &self.spk_list,
+      gens,
+      &statement,
+      self.nonce.as_deref(),
+      self.encoding == PoKEncoding::Commitment,
+    )?;
+
+    // Check that sum(I_i) - total_amount*H = y*G' for a y known to the exchange
+    let reserve_point = self.reserve_point(gens)?;
+    if !DiscreteLogPoK::verify_dlog_spk(
+      secp_inst,
+      &reserve_point,
+      &gens.keyimage_gen,
+      &statement,
+      self.nonce.as_deref(),
+      &self.reserve_spk,
+    )? {
+      return Err(Error::InvalidReserveProof);
+    }
+
+    Ok(())
+  }
+
+  /// Hashes the generators, the proof context, the declared total, the
+  /// anonymity list size and every output followed by its key image. Every
+  /// challenge in the proof commits to this digest, so a proof for a single
+  /// output cannot be moved into another proof.
+  pub fn statement_digest(&self) -> [u8; 32] {
+    let mut hasher = RevelioStatementHasher::new(
+      &self.blinding_basepoint,
+      &self.value_basepoint,
+      &self.keyimage_basepoint,
+      &self.context,
+      self.total_amount,
+      self.anon_list.len() as u64,
+    );
+    for (output, keyimage) in self.anon_list.iter().zip(&self.keyimage_list) {
+      hasher.add_output(output, keyimage);
+    }
+    hasher.finish()
+  }
+
+  /// Calculates sum(I_i) - total_amount*H which should be a multiple of G'
+  fn reserve_point(&self, gens: &Generators) -> Result<PublicKey, Error> {
+    let secp_inst = secp_context();
+
+    let mut sum_keyimages = self.keyimage_list[0];
+    for keyimage in &self.keyimage_list[1..] {
+      sum_keyimages = PublicKey::from_combination(secp_inst, vec![&sum_keyimages, keyimage])?;  // sum_keyimages += keyimage
+    }
+
+    subtract_amount(secp_inst, sum_keyimages, self.total_amount, gens.value_gen)
+  }
+}
+
+/// Binary layout: version, tag, G, H, G', context, nonce, total_amount,
+/// reserve_spk, anon_list, keyimage_list, encoding, spk_list
+impl Writeable for RevelioProof {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_header(REVELIO_PROOF_TAG);
+    writer.write_point(&self.blinding_basepoint);
+    writer.write_point(&self.value_basepoint);
+    writer.write_point(&self.keyimage_basepoint);
+    self.context.write(writer);
+    match self.nonce {
+      Some(ref nonce) => {
+        writer.write_u8(1);
+        writer.write_var_bytes(nonce);
+      },
+      None => writer.write_u8(0),
+    }
+    writer.write_u64(self.total_amount);
+    self.reserve_spk.write(writer);
+    writer.write_point_list(&self.anon_list);
+    writer.write_point_list(&self.keyimage_list);
+    let encoding = self.written_encoding();
+    writer.write_u8(encoding.to_u8());
+    writer.write_u64(self.spk_list.len() as u64);
+    for rspk in &self.spk_list {
+      rspk.write(writer);
+      if let (PoKEncoding::Commitment, Some(commitments)) = (encoding, rspk.commitments()) {
+        commitments.write(writer);
+      }
+    }
+  }
+}
+
+impl Readable for RevelioProof {
+  fn read(reader: &mut Reader) -> Result<RevelioProof, ser::Error> {
+    reader.read_header(REVELIO_PROOF_TAG)?;
+    let blinding_basepoint = reader.read_point()?;
+    let value_basepoint = reader.read_point()?;
+    let keyimage_basepoint = reader.read_point()?;
+    let context = ProofContext::read(reader)?;
+    let nonce = match reader.read_u8()? {
+      0 => None,
+      1 => Some(reader.read_var_bytes()?),
+      flag => return Err(ser::Error::InvalidFlag(flag)),
+    };
+    let total_amount = reader.read_u64()?;
+    let reserve_spk = DiscreteLogPoK::read(reader)?;
+    let anon_list = reader.read_point_list()?;
+    let keyimage_list = reader.read_point_list()?;
+    let encoding = PoKEncoding::from_u8(reader.read_u8()?)?;
+    let spk_list = match encoding {
+      PoKEncoding::Challenge => reader.read_list(5 * SCALAR_SIZE)?,
+      PoKEncoding::Commitment => {
+        let len = reader.read_list_len(5 * SCALAR_SIZE + 3 * POINT_SIZE)?;
+        let mut spk_list = Vec::with_capacity(len);
+        for _i in 0..len {
+          spk_list.push(RevelioPoK::read_with_commitments(reader)?);
+        }
+        spk_list
+      },
+    };
+
+    Ok(RevelioProof {
+      anon_list,
+      keyimage_list,
+      spk_list,
+      context,
+      nonce,
+      total_amount,
+      reserve_spk,
+      encoding,
+      blinding_basepoint,
+      value_basepoint,
+      keyimage_basepoint,
+    })
+  }
+}
+
+impl From<&RevelioProof> for RevelioProofJson {
+  fn from(proof: &RevelioProof) -> RevelioProofJson {
+    let encoding = proof.written_encoding();
+    let mut spk_list: Vec<RevelioPoKJson> = proof.spk_list.iter().map(|rspk| rspk.into()).collect();
+    if encoding == PoKEncoding::Challenge {
+      for rspk in &mut spk_list {
+        rspk.commitments = None;
+      }
+    }
+
+    RevelioProofJson {
+      version: PROTOCOL_VERSION,
+      proof_type: REVELIO_PROOF_TYPE.to_string(),
+      anon_list_size: proof.anon_list.len(),
+      blinding_basepoint: point_to_hex(&proof.blinding_basepoint),
+      value_basepoint: point_to_hex(&proof.value_basepoint),
+      keyimage_basepoint: point_to_hex(&proof.keyimage_basepoint),
+      exchange_id: proof.context.exchange_id.clone(),
+      block_height: proof.context.block_height,
+      nonce: proof.nonce.as_ref().map(hex::encode),
+      total_amount: proof.total_amount,
+      reserve_spk: (&proof.reserve_spk).into(),
+      anon_list: point_list_to_hex(&proof.anon_list),
+      keyimage_list: point_list_to_hex(&proof.keyimage_list),
+      spk_encoding: encoding.as_str().to_string(),
+      spk_list,
+    }
+  }
+}
+
+impl TryFrom<&RevelioProofJson> for RevelioProof {
+  type Error = json::Error;
+
+  fn try_from(proof: &RevelioProofJson) -> Result<RevelioProof, json::Error> {
+    json::check_header(proof.version, &proof.proof_type, REVELIO_PROOF_TYPE)?;
+    json::check_list_size("anon_list", proof.anon_list_size, proof.anon_list.len())?;
+    json::check_list_size("keyimage_list", proof.anon_list_size, proof.keyimage_list.len())?;
+    json::check_list_size("spk_list", proof.anon_list_size, proof.spk_list.len())?;
+    let encoding: PoKEncoding = proof.spk_encoding.parse()?;
+    let with_commitments = encoding == PoKEncoding::Commitment;
+    if proof.spk_list.iter().any(|rspk| rspk.commitments.is_some() != with_commitments) {
+      return Err(json::Error::Metadata(format!("spk_list is not in the {} form", encoding.as_str())));
+    }
+
+    Ok(RevelioProof {
+      anon_list: point_list_from_hex(&proof.anon_list)?,
+      keyimage_list: point_list_from_hex(&proof.keyimage_list)?,
+      spk_list: proof.spk_list.iter().map(RevelioPoK::try_from).collect::<Result<_, _>>()?,
+      context: ProofContext {
+        exchange_id: proof.exchange_id.clone(),
+        block_height: proof.block_height,
+      },
+      nonce: proof.nonce.as_ref().map(hex::decode).transpose()?,
+      total_amount: proof.total_amount,
+      reserve_spk: DiscreteLogPoK::try_from(&proof.reserve_spk)?,
+      encoding,
+      blinding_basepoint: point_from_hex(&proof.blinding_basepoint)?,
+      value_basepoint: point_from_hex(&proof.value_basepoint)?,
+      keyimage_basepoint: point_from_hex(&proof.keyimage_basepoint)?,
+    })
+  }
+}
+
+impl RevelioProof {
+  /// Encodes the proof as pretty-printed JSON with hex points and scalars
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(&RevelioProofJson::from(self)).unwrap()
+  }
 
-    true
+  /// Decodes a proof from its JSON encoding
+  pub fn from_json(s: &str) -> Result<RevelioProof, json::Error> {
+    let proof: RevelioProofJson = serde_json::from_str(s)?;
+    RevelioProof::try_from(&proof)
   }
 }
 
@@ -195,134 +1021,384 @@ pub struct RevelioGrinExchange {
   revelio_proof: RevelioProof,
   own_keys: Vec<SecretKey>,
   own_amounts: Vec<u64>,
-  _decoy_keys_seed: SecretKey,
+  decoy_seed: DecoySeed,
   decoy_keys: Vec<SecretKey>,
+  nonce_mode: NonceMode,
 }
 
 impl RevelioGrinExchange {
-  pub fn new(alist_size: usize, olist_size: usize) -> RevelioGrinExchange  {
+  /// Creates an exchange owning olist_size random outputs hidden among
+  /// alist_size - olist_size random decoy outputs, for simulations
+  pub fn new(
+    alist_size: usize,
+    olist_size: usize,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioGrinExchange, Error>  {
 
-    let mut revproof = RevelioProof::new(alist_size);
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-    let mut okeys = Vec::new();
-    let mut amounts = vec![0u64; alist_size];
-    let mut dkeys = vec![ZERO_KEY; alist_size];
+    let (decoy_list, own_outputs) = random_outputs(alist_size, olist_size, rng)?;
+    let decoy_seed = DecoySeed::random(rng);
+    RevelioGrinExchange::from_outputs(decoy_list, own_outputs, decoy_seed, rng)
+  }
 
-    let mut rng = thread_rng();
+  /// Creates an exchange with random outputs like new, deriving the decoy
+  /// keys from a given seed instead of a fresh one
+  pub fn new_with_seed(
+    alist_size: usize,
+    olist_size: usize,
+    decoy_seed: DecoySeed,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioGrinExchange, Error>  {
 
-    for i in 0..alist_size {
-      if i < olist_size {
-        okeys.push(SecretKey::new(&secp_inst, &mut rng));
-      } else {
-        okeys.push(ZERO_KEY)
-      }
+    let (decoy_list, own_outputs) = random_outputs(alist_size, olist_size, rng)?;
+    RevelioGrinExchange::from_outputs(decoy_list, own_outputs, decoy_seed, rng)
+  }
+
+  /// Creates an exchange from an anonymity list of output commitments and its
+  /// own outputs given as (commitment, blinding factor, amount) triples. Own
+  /// outputs which are missing from the anonymity list are inserted into it at
+  /// random positions.
+  ///
+  /// The decoy keys are derived from decoy_seed and the output alone. Every
+  /// proof of an exchange must use the same seed: with a fresh seed only the
+  /// key images of the own outputs, see [`RevelioGrinExchange::create_keyimage`],
+  /// would persist, and intersecting two proofs would reveal them.
+  pub fn from_outputs(
+    anon_list: Vec<PublicKey>,
+    own_outputs: Vec<OwnOutput>,
+    decoy_seed: DecoySeed,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioGrinExchange, Error> {
+
+    if let Some(i) = find_identity(&anon_list) {
+      return Err(Error::IdentityOutput(i));
+    }
+    if let Some((first, second)) = find_duplicate(&anon_list) {
+      return Err(Error::DuplicateOutput { first, second });
     }
 
-    // Randomly permuting the own outputs
-    okeys.shuffle(&mut rng);
+    let secp_inst = secp_context();
+    let mut alist = anon_list;
+    let mut okeys = vec![ZERO_KEY; alist.len()];
+    let mut amounts = vec![0u64; alist.len()];
 
-    // Long-term secret key used to seed creation of decoy keys
-    let dkeys_seed = SecretKey::new(&secp_inst, &mut rng);
+    for (i, (commitment, blinding, amount)) in own_outputs.into_iter().enumerate() {
+      check_own_output(secp_inst, i, &commitment, &blinding, amount)?;
+      match alist.iter().position(|c| *c == commitment) {
+        Some(j) => {
+          if okeys[j] != ZERO_KEY {
+            return Err(Error::InvalidOwnOutput { index: i, reason: "appears more than once" });
+          }
+          okeys[j] = blinding;
+          amounts[j] = amount;
+        },
+        None => {
+          let j = rng.gen_range(0, alist.len() + 1);
+          alist.insert(j, commitment);
+          okeys.insert(j, blinding);
+          amounts.insert(j, amount);
+        },
+      } // end match
+    } // end for
 
-    // Initialize SHA256 to generate decoy keys
-    let mut hasher = Sha256::new();
+    if alist.is_empty() {
+      return Err(Error::EmptyList);
+    }
+
+    let alist_size = alist.len();
+    let mut revproof = RevelioProof::new(alist_size);
+    let mut dkeys = vec![ZERO_KEY; alist_size];
+    revproof.anon_list = alist;
 
-    for i in 0..alist_size {
+    // Key images and decoy keys of the outputs, computed independently of each other
+    let keyimages = parallel::map_indices(alist_size, |i| -> Result<(PublicKey, SecretKey), Error> {
       if okeys[i] != ZERO_KEY {
-        amounts[i] = rng.gen_range(1, MAX_AMOUNT_PER_OUTPUT);
-        revproof.anon_list[i] = Secp256k1::commit(&secp_inst, amounts[i], okeys[i]).unwrap()
-                                  .to_pubkey(&secp_inst).unwrap();
-        revproof.keyimage_list[i] = RevelioGrinExchange ::create_keyimage(amounts[i], okeys[i]); // I_i = alpha*G' + beta*H
+        let keyimage = RevelioGrinExchange::create_keyimage(amounts[i], okeys[i].clone())?; // I_i = alpha*G' + beta*H
+        Ok((keyimage, ZERO_KEY))
       } else {
-        let temp_sk = SecretKey::new(&secp_inst, &mut rng);
-        revproof.anon_list[i] = PublicKey::from_secret_key(&secp_inst, &temp_sk).unwrap();
-        hasher.input(dkeys_seed);                                                  // Hash k_exch
-        hasher.input(revproof.anon_list[i].serialize_vec(&secp_inst, true));       // Hash C_i
-        dkeys[i] = SecretKey::from_slice(&secp_inst, &hasher.clone().result()).unwrap();
-        revproof.keyimage_list[i] = RevelioGrinExchange ::create_keyimage(0, dkeys[i]); // I_i = SHA256(k_exch, C_i)*G' + 0*H
-        hasher.reset();
+        let dkey = decoy_seed.decoy_key(&revproof.anon_list[i]);
+        let keyimage = RevelioGrinExchange::create_keyimage(0, dkey.clone())?;     // I_i = k_i*G' + 0*H
+        Ok((keyimage, dkey))
       }
+    });
+    for (i, result) in keyimages.into_iter().enumerate() {
+      let (keyimage, dkey) = result?;
+      revproof.keyimage_list[i] = keyimage;
+      dkeys[i] = dkey;
     }
 
-    revproof.blinding_basepoint = PublicKey::from_slice(&secp_inst, &GENERATOR_G).unwrap();
-    revproof.value_basepoint = PublicKey::from_slice(&secp_inst, &GENERATOR_H).unwrap();
-    revproof.keyimage_basepoint = PublicKey::from_slice(&secp_inst, &GENERATOR_J_COMPR).unwrap();
+    let gens = Generators::new();
+    revproof.blinding_basepoint = gens.blinding_gen;
+    revproof.value_basepoint = gens.value_gen;
+    revproof.keyimage_basepoint = gens.keyimage_gen;
 
-    RevelioGrinExchange  {
+    Ok(RevelioGrinExchange  {
       anon_list_size: alist_size,
       revelio_proof: revproof,
       own_keys: okeys,
       own_amounts: amounts,
-      _decoy_keys_seed: dkeys_seed,
+      decoy_seed,
       decoy_keys: dkeys,
-    }
+      nonce_mode: NonceMode::default(),
+    })
   }
 
-  /// Generating key image commitment
-  pub fn create_keyimage(amount: u64, blinding: SecretKey) -> PublicKey {
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-    let keyimage_gen = PublicKey::from_slice(&secp_inst, &GENERATOR_J_COMPR).unwrap();
-    let value_gen = PublicKey::from_slice(&secp_inst, &GENERATOR_H).unwrap();
+  /// Generating key image commitment alpha*G' + beta*H of an own output.
+  ///
+  /// The key image is fixed by the opening of the output, so every proof
+  /// which counts the output publishes the same key image for it, whichever
+  /// exchange makes the proof and in whichever epoch. Decoy key images
+  /// k_i*G' depend on the decoy seed of the exchange instead.
+  pub fn create_keyimage(amount: u64, blinding: SecretKey) -> Result<PublicKey, Error> {
+    let secp_inst = secp_context();
+    let gens = Generators::new();
 
-    let mut blind_gp = keyimage_gen.clone();
-    blind_gp.mul_assign(&secp_inst, &blinding).unwrap();
+    let mut blind_gp = gens.keyimage_gen;
+    blind_gp.mul_assign(secp_inst, &blinding)?;
 
-    let keyimage = if amount == 0u64 {
-      blind_gp
+    if amount == 0u64 {
+      Ok(blind_gp)
     } else {
-      let amount_sk = RevelioPoK::amount_to_key(&secp_inst, amount);
-      let mut amount_pk = value_gen.clone();
-      amount_pk.mul_assign(&secp_inst, &amount_sk).unwrap();
+      let amount_sk = RevelioPoK::amount_to_key(secp_inst, amount)?;
+      let mut amount_pk = gens.value_gen;
+      amount_pk.mul_assign(secp_inst, &amount_sk)?;
+
+      Ok(PublicKey::from_combination(secp_inst, vec![&blind_gp, &amount_pk])?)
+    }
+  }
+
+  /// The seed the decoy keys are derived from, to be stored with the
+  /// exchange's keys or disclosed to an auditor
+  pub fn decoy_seed(&self) -> &DecoySeed {
+    &self.decoy_seed
+  }
+
+  /// Sets the exchange id and block height that subsequent proofs are bound to
+  pub fn set_context(&mut self, context: ProofContext) {
+    self.revelio_proof.context = context;
+  }
+
+  /// Sets the verifier nonce that subsequent proofs are bound to
+  pub fn set_nonce(&mut self, nonce: Option<Vec<u8>>) {
+    self.revelio_proof.nonce = nonce;
+  }
+
+  /// Sets how the random scalars of subsequent proofs are derived
+  pub fn set_nonce_mode(&mut self, mode: NonceMode) {
+    self.nonce_mode = mode;
+  }
+
+  /// Sets how the output proofs of subsequent proofs are encoded
+  pub fn set_encoding(&mut self, encoding: PoKEncoding) {
+    self.revelio_proof.encoding = encoding;
+  }
+
+  /// Creates a fresh proof for the context and nonce in an auditor's request
+  pub fn respond_to_challenge(
+    &mut self,
+    request: &ChallengeRequest,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioProof, Error> {
+    self.set_context(request.context.clone());
+    self.set_nonce(Some(request.nonce.clone()));
+    self.generate_proof(rng)
+  }
+
+  pub fn generate_proof(&mut self, rng: &mut (impl RngCore + CryptoRng)) -> Result<RevelioProof, Error> {
+    let statement = self.start_proof()?;
+    let mut run_seed = [0u8; 32];
+    rng.fill_bytes(&mut run_seed);
+    let (seeds, mut run_rng) = self.output_seeds(run_seed);
 
-      PublicKey::from_combination(&secp_inst, vec![&blind_gp, &amount_pk]).unwrap()
+    self.revelio_proof.spk_list = self.create_output_proofs(0, self.anon_list_size, &statement, &seeds)?;
+    self.finish_proof(&statement, &mut run_rng)
+  } // end generate_proof
+
+  /// Generates a proof like generate_proof, recording the output proofs in a
+  /// checkpoint file as they are completed.
+  ///
+  /// If the file holds a checkpoint of the same statement, generation resumes
+  /// after the last output proof recorded in it and the proof is
+  /// byte-identical to the one an uninterrupted run would have produced. The
+  /// exchange must therefore be recreated with the same outputs and decoy
+  /// keys after a crash, i.e. with from_outputs and the DecoySeed of the
+  /// interrupted run. The decoy key images enter the statement digest, so an
+  /// exchange built with another seed, as new does with a fresh one, gets
+  /// CheckpointMismatch. The output proofs read from the checkpoint are
+  /// verified before generation resumes, and one which does not verify is
+  /// reported as InvalidOutputProof. The checkpoint holds the seed of the run
+  /// in plain text, so open refuses a file whose mode on Unix is not 0600.
+  /// The checkpoint is left in place so that the work is not lost before the
+  /// proof has been stored; the caller removes it.
+  pub fn generate_proof_with_checkpoint(
+    &mut self,
+    checkpoint_path: &Path,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioProof, Error> {
+    let statement = self.start_proof()?;
+    let mut run_seed = [0u8; 32];
+    rng.fill_bytes(&mut run_seed);
+    let mut checkpoint = Checkpoint::open(
+                           checkpoint_path,
+                           &statement,
+                           self.revelio_proof.nonce.as_deref(),
+                           self.nonce_mode,
+                           run_seed,
+                         )?;
+    if checkpoint.spks.len() > self.anon_list_size {
+      return Err(Error::CheckpointMismatch);
+    }
+
+    // The recorded output proofs go into the proof unchecked otherwise
+    let gens = Generators {
+      blinding_gen: self.revelio_proof.blinding_basepoint,
+      value_gen: self.revelio_proof.value_basepoint,
+      keyimage_gen: self.revelio_proof.keyimage_basepoint,
     };
-    keyimage
+    verify_output_proofs(
+      &self.revelio_proof.anon_list,
+      &self.revelio_proof.keyimage_list,
+      &checkpoint.spks,
+      &gens,
+      &statement,
+      self.revelio_proof.nonce.as_deref(),
+      true,
+    )?;
+    let (seeds, mut run_rng) = self.output_seeds(checkpoint.run_seed);
+
+    while checkpoint.spks.len() < self.anon_list_size {
+      let start = checkpoint.spks.len();
+      let end = usize::min(start + CHECKPOINT_INTERVAL, self.anon_list_size);
+      let spks = self.create_output_proofs(start, end, &statement, &seeds)?;
+      checkpoint.append(spks)?;
+    }
+    self.revelio_proof.spk_list = checkpoint.spks;
+    self.finish_proof(&statement, &mut run_rng)
   }
 
-  pub fn generate_proof(&mut self) -> RevelioProof {
+  /// Fixes the declared total and returns the statement digest
+  fn start_proof(&mut self) -> Result<[u8; 32], Error> {
+    let mut sum_amount = 0u64;
 
-    for i in 0..self.anon_list_size {
+    // The declared total is part of the statement, so it is needed before any challenge
+    for amount in &self.own_amounts {
+      sum_amount = sum_amount.checked_add(*amount).ok_or(Error::AmountOverflow)?;
+    }
+    self.revelio_proof.total_amount = sum_amount;
+    Ok(self.revelio_proof.statement_digest())
+  }
+
+  /// Derives the seed of every output proof from the seed of the run. The
+  /// returned RNG continues after the output seeds and is used for the proof
+  /// of the total amount.
+  fn output_seeds(&self, run_seed: [u8; 32]) -> (Vec<[u8; 32]>, StdRng) {
+    let mut run_rng = StdRng::from_seed(run_seed);
+    let seeds = (0..self.anon_list_size).map(|_| {
+      let mut seed = [0u8; 32];
+      run_rng.fill_bytes(&mut seed);
+      seed
+    }).collect();
+    (seeds, run_rng)
+  }
+
+  /// Creates the proofs of the outputs at indices start..end
+  fn create_output_proofs(
+    &self,
+    start: usize,
+    end: usize,
+    statement: &[u8; 32],
+    seeds: &[[u8; 32]],
+  ) -> Result<Vec<RevelioPoK>, Error> {
+    let secp_inst = secp_context();
+
+    // Each output gets its own RNG, so the proof does not depend on the
+    // order in which the threads create the output proofs
+    let spks = parallel::map_indices(end - start, |k| {
+      let i = start + k;
+      let mut output_rng = StdRng::from_seed(seeds[i]);
       if self.own_keys[i] != ZERO_KEY {
-        self.revelio_proof.spk_list[i] = RevelioPoK::create_spk_from_representation(
-                                            self.revelio_proof.anon_list[i],
-                                            self.revelio_proof.keyimage_list[i],
-                                            self.own_keys[i],
-                                            self.own_amounts[i],
-                                            self.revelio_proof.blinding_basepoint,  // G
-                                            self.revelio_proof.value_basepoint,     // H
-                                            self.revelio_proof.keyimage_basepoint,  // G'
-                                          );
+        RevelioPoK::create_spk_from_representation(
+          secp_inst,
+          self.revelio_proof.anon_list[i],
+          self.revelio_proof.keyimage_list[i],
+          self.own_keys[i].clone(),
+          self.own_amounts[i],
+          self.revelio_proof.blinding_basepoint,  // G
+          self.revelio_proof.value_basepoint,     // H
+          self.revelio_proof.keyimage_basepoint,  // G'
+          statement,
+          self.revelio_proof.nonce.as_deref(),
+          self.nonce_mode,
+          &mut output_rng,
+        )
       } else {
-        self.revelio_proof.spk_list[i] = RevelioPoK::create_spk_from_decoykey(
-                                            self.revelio_proof.anon_list[i],
-                                            self.revelio_proof.keyimage_list[i],
-                                            self.decoy_keys[i],
-                                            self.revelio_proof.blinding_basepoint,  // G
-                                            self.revelio_proof.value_basepoint,     // H
-                                            self.revelio_proof.keyimage_basepoint,  // G'
-                                          );
+        RevelioPoK::create_spk_from_decoykey(
+          secp_inst,
+          self.revelio_proof.anon_list[i],
+          self.revelio_proof.keyimage_list[i],
+          self.decoy_keys[i].clone(),
+          self.revelio_proof.blinding_basepoint,  // G
+          self.revelio_proof.value_basepoint,     // H
+          self.revelio_proof.keyimage_basepoint,  // G'
+          statement,
+          self.revelio_proof.nonce.as_deref(),
+          self.nonce_mode,
+          &mut output_rng,
+        )
       } // end if-else
+    });
+    spks.into_iter().collect()
+  }
+
+  /// Creates the proof of the total amount once all output proofs are in place
+  fn finish_proof(&mut self, statement: &[u8; 32], rng: &mut StdRng) -> Result<RevelioProof, Error> {
+    let secp_inst = secp_context();
+    let mut sum_keys = ZERO_KEY;
+
+    for i in 0..self.anon_list_size {
+      if self.own_keys[i] != ZERO_KEY {
+        sum_keys.add_assign(secp_inst, &self.own_keys[i])?;
+      } else {
+        sum_keys.add_assign(secp_inst, &self.decoy_keys[i])?;
+      }
     } // end for
 
-    RevelioProof {
-      anon_list: self.revelio_proof.anon_list.clone(),
-      keyimage_list: self.revelio_proof.keyimage_list.clone(),
-      spk_list: self.revelio_proof.spk_list.clone(),
-      blinding_basepoint: self.revelio_proof.blinding_basepoint,
-      value_basepoint: self.revelio_proof.value_basepoint,
-      keyimage_basepoint: self.revelio_proof.keyimage_basepoint,
-    }
-  } // end generate_proof
+    // Proving knowledge of y = sum of own and decoy keys in sum(I_i) - sum_amount*H = y*G'
+    let reserve_point = self.revelio_proof.reserve_point(&Generators::new())?;
+    self.revelio_proof.reserve_spk = DiscreteLogPoK::create_dlog_spk(
+                                        secp_inst,
+                                        reserve_point,
+                                        sum_keys,
+                                        self.revelio_proof.keyimage_basepoint,  // G'
+                                        statement,
+                                        self.revelio_proof.nonce.as_deref(),
+                                        self.nonce_mode,
+                                        rng,
+                                      )?;
+
+    Ok(self.revelio_proof.clone())
+  }
 
 } // end RevelioGrinExchange implementation
 
 #[cfg(test)]
 mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
   use secp256k1zkp as secp;
   use secp::Secp256k1;
-  use secp::key::{PublicKey, ZERO_KEY, ONE_KEY};
-  use super::{GENERATOR_G, GENERATOR_H, GENERATOR_J_COMPR};
-  use super::RevelioGrinExchange ;
+  use secp::key::{SecretKey, PublicKey, ZERO_KEY, ONE_KEY};
+  use super::{GENERATOR_G, GENERATOR_H, GENERATOR_GPRIME_COMPR};
+  use super::{RevelioGrinExchange, SimpleGrinExchange, RevelioProof, Generators, ProofContext, MINUS_ONE_KEY};
+  use super::DecoySeed;
+  use super::ChallengeRequest;
+  use super::derive_keyimage_generator;
+  use super::super::context::secp_context;
+  use super::super::error::Error;
+  use super::super::nizk::PoKEncoding;
+  use super::super::parallel;
+  use super::super::ser;
+  use super::super::transcript::NonceMode;
+  use std::fs::{self, OpenOptions};
 
 
   #[test]
@@ -332,14 +1408,395 @@ mod test {
                               .to_pubkey(&secp_inst).unwrap();                  // 1*G + 0*H
     let value_gen1 = Secp256k1::commit(&secp_inst, 1, ZERO_KEY).unwrap()
                               .to_pubkey(&secp_inst).unwrap();                  // 0*G + 1*H
-    let keyim_gen1 = RevelioGrinExchange ::create_keyimage(0, ONE_KEY);              // 1*G' +0*H
+    let keyim_gen1 = RevelioGrinExchange::create_keyimage(0, ONE_KEY).unwrap();      // 1*G' +0*H
 
     let blind_gen2 = PublicKey::from_slice(&secp_inst, &GENERATOR_G).unwrap();
     let value_gen2 = PublicKey::from_slice(&secp_inst, &GENERATOR_H).unwrap();
-    let keyim_gen2 = PublicKey::from_slice(&secp_inst, &GENERATOR_J_COMPR).unwrap();
+    let keyim_gen2 = PublicKey::from_slice(&secp_inst, &GENERATOR_GPRIME_COMPR).unwrap();
 
     assert!(blind_gen1 == blind_gen2);
     assert!(value_gen1 == value_gen2);
     assert!(keyim_gen1 == keyim_gen2);
   }
+
+  #[test]
+  fn check_keyimage_generator_derivation() {
+    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+    let keyim_gen = PublicKey::from_slice(&secp_inst, &GENERATOR_GPRIME_COMPR).unwrap();
+
+    assert!(derive_keyimage_generator() == keyim_gen);
+    assert!(Generators::new().keyimage_gen == keyim_gen);
+    assert!(keyim_gen != PublicKey::from_slice(&secp_inst, &GENERATOR_G).unwrap());
+    assert!(keyim_gen != PublicKey::from_slice(&secp_inst, &GENERATOR_H).unwrap());
+  }
+
+  #[test]
+  fn revelio_proof_total_amount() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut grin_exch = RevelioGrinExchange::new(10, 4, &mut rng).unwrap();
+    let mut revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(revelio_proof.verify().is_ok());
+
+    // The total is part of the statement which every challenge commits to
+    revelio_proof.total_amount += 1;
+    assert!(revelio_proof.verify() == Err(Error::InvalidOutputProof(0)));
+  }
+
+  #[test]
+  fn simple_proof_total_amount() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let mut grin_exch = SimpleGrinExchange::new(5, &mut rng).unwrap();
+    let mut simple_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(simple_proof.verify().is_ok());
+
+    simple_proof.total_amount -= 1;
+    assert!(simple_proof.verify() == Err(Error::InvalidReserveProof));
+  }
+
+  #[test]
+  fn reject_noncanonical_generators() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+    let gens = Generators::new();
+
+    let mut grin_exch = RevelioGrinExchange::new(4, 2, &mut rng).unwrap();
+    let mut revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(revelio_proof.verify_with_generators(&gens).is_ok());
+
+    // A prover-chosen G' whose discrete log w.r.t. G is known
+    let mut fake_gen = gens.blinding_gen;
+    fake_gen.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();
+    revelio_proof.keyimage_basepoint = fake_gen;
+    assert!(revelio_proof.verify_with_generators(&gens) == Err(Error::NonCanonicalGenerators));
+
+    let mut grin_exch = SimpleGrinExchange::new(2, &mut rng).unwrap();
+    let mut simple_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    simple_proof.value_basepoint = fake_gen;
+    assert!(simple_proof.verify() == Err(Error::NonCanonicalGenerators));
+  }
+
+  #[test]
+  fn exchange_from_outputs() {
+    let mut rng = StdRng::seed_from_u64(6);
+    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+
+    let blind1 = SecretKey::new(&secp_inst, &mut rng);
+    let blind2 = SecretKey::new(&secp_inst, &mut rng);
+    let output1 = Secp256k1::commit(&secp_inst, 30, blind1.clone()).unwrap()
+                    .to_pubkey(&secp_inst).unwrap();
+    let output2 = Secp256k1::commit(&secp_inst, 12, blind2.clone()).unwrap()
+                    .to_pubkey(&secp_inst).unwrap();
+
+    // Anonymity list containing output1 but not output2
+    let mut anon_list = vec![output1];
+    for _i in 0..3 {
+      let sk = SecretKey::new(&secp_inst, &mut rng);
+      anon_list.push(PublicKey::from_secret_key(&secp_inst, &sk).unwrap());
+    }
+
+    let seed = DecoySeed::random(&mut rng);
+    let own_outputs = vec![(output1, blind1.clone(), 30), (output2, blind2.clone(), 12)];
+    let mut grin_exch = RevelioGrinExchange::from_outputs(anon_list.clone(), own_outputs, seed.clone(), &mut rng).unwrap();
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(revelio_proof.anon_list.len() == 5);
+    assert!(revelio_proof.anon_list.contains(&output2));
+    assert!(revelio_proof.total_amount == 42);
+    assert!(revelio_proof.verify().is_ok());
+
+    let wrong_amount = vec![(output1, blind1.clone(), 31)];
+    assert!(RevelioGrinExchange::from_outputs(anon_list.clone(), wrong_amount, seed.clone(), &mut rng).is_err());
+    let repeated = vec![(output1, blind1.clone(), 30), (output1, blind1.clone(), 30)];
+    assert!(RevelioGrinExchange::from_outputs(anon_list, repeated, seed, &mut rng).is_err());
+
+    let mut grin_exch = SimpleGrinExchange::from_outputs(vec![(output1, blind1, 30), (output2, blind2.clone(), 12)]).unwrap();
+    let simple_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(simple_proof.total_amount == 42);
+    assert!(simple_proof.verify().is_ok());
+    assert!(SimpleGrinExchange::from_outputs(vec![(output1, blind2, 30)]).is_err());
+  }
+
+  #[test]
+  fn reject_malformed_proofs() {
+    let mut rng = StdRng::seed_from_u64(7);
+    assert!(RevelioGrinExchange::new(2, 3, &mut rng).err() ==
+            Some(Error::TooManyOwnOutputs { own_list_size: 3, anon_list_size: 2 }));
+    assert!(RevelioGrinExchange::from_outputs(vec![], vec![], DecoySeed::random(&mut rng), &mut rng).err() ==
+            Some(Error::EmptyList));
+
+    let mut grin_exch = RevelioGrinExchange::new(4, 2, &mut rng).unwrap();
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+
+    let mut short_keyimages = revelio_proof.clone();
+    short_keyimages.keyimage_list.pop();
+    assert!(short_keyimages.verify() == Err(Error::ListSizeMismatch { expected: 4, found: 3 }));
+
+    let mut empty = revelio_proof.clone();
+    empty.anon_list.clear();
+    empty.keyimage_list.clear();
+    empty.spk_list.clear();
+    assert!(empty.verify() == Err(Error::EmptyList));
+
+    let mut swapped = revelio_proof;
+    swapped.keyimage_list.swap(0, 1);
+    assert!(swapped.verify() == Err(Error::InvalidOutputProof(0)));
+  }
+
+  #[test]
+  fn bind_challenges_to_statement() {
+    let mut rng = StdRng::seed_from_u64(8);
+    let mut grin_exch = RevelioGrinExchange::new(4, 2, &mut rng).unwrap();
+    grin_exch.set_context(ProofContext { exchange_id: "exchange-a".to_string(), block_height: 100 });
+    let proof1 = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(proof1.verify().is_ok());
+
+    grin_exch.set_context(ProofContext { exchange_id: "exchange-a".to_string(), block_height: 101 });
+    let proof2 = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(proof2.verify().is_ok());
+
+    // A proof for an output taken from a proof over a different statement
+    let mut mixed = proof2.clone();
+    mixed.spk_list[1] = proof1.spk_list[1].clone();
+    assert!(mixed.verify() == Err(Error::InvalidOutputProof(1)));
+    let mut mixed = proof2.clone();
+    mixed.reserve_spk = proof1.reserve_spk.clone();
+    assert!(mixed.verify() == Err(Error::InvalidReserveProof));
+
+    // Changing the context invalidates the proof
+    let mut relabelled = proof2.clone();
+    relabelled.context.exchange_id = "exchange-b".to_string();
+    assert!(relabelled.verify() == Err(Error::InvalidOutputProof(0)));
+    let mut relabelled = proof2;
+    relabelled.context.block_height = 100;
+    assert!(relabelled.verify() == Err(Error::InvalidOutputProof(0)));
+  }
+
+  #[test]
+  fn challenge_request_flow() {
+    let mut rng = StdRng::seed_from_u64(9);
+    let context = ProofContext { exchange_id: "exchange-a".to_string(), block_height: 100 };
+    let request = ChallengeRequest::new(context.clone(), &mut rng);
+    let mut grin_exch = RevelioGrinExchange::new(4, 2, &mut rng).unwrap();
+
+    // A proof made before the request does not answer it
+    grin_exch.set_context(context.clone());
+    let old_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(old_proof.verify().is_ok());
+    assert!(matches!(request.verify_response(&old_proof), Err(Error::ChallengeMismatch(_))));
+
+    let proof = grin_exch.respond_to_challenge(&request, &mut rng).unwrap();
+    assert!(proof.nonce.as_ref() == Some(&request.nonce));
+    assert!(request.verify_response(&proof).is_ok());
+
+    // Neither a response to another request nor a proof with a swapped nonce answers it
+    let other_request = ChallengeRequest::new(context, &mut rng);
+    assert!(request.nonce != other_request.nonce);
+    assert!(matches!(other_request.verify_response(&proof), Err(Error::ChallengeMismatch(_))));
+    let mut replayed = proof;
+    replayed.nonce = Some(other_request.nonce.clone());
+    assert!(other_request.verify_response(&replayed) == Err(Error::InvalidOutputProof(0)));
+  }
+
+  #[test]
+  fn reject_duplicate_entries() {
+    let mut rng = StdRng::seed_from_u64(10);
+    let mut grin_exch = RevelioGrinExchange::new(5, 2, &mut rng).unwrap();
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+
+    let mut repeated_output = revelio_proof.clone();
+    repeated_output.anon_list[3] = repeated_output.anon_list[1];
+    repeated_output.keyimage_list[3] = repeated_output.keyimage_list[1];
+    repeated_output.spk_list[3] = repeated_output.spk_list[1].clone();
+    assert!(repeated_output.verify() == Err(Error::DuplicateOutput { first: 1, second: 3 }));
+
+    let mut repeated_keyimage = revelio_proof.clone();
+    repeated_keyimage.keyimage_list[4] = repeated_keyimage.keyimage_list[0];
+    assert!(repeated_keyimage.verify() == Err(Error::DuplicateKeyImage { first: 0, second: 4 }));
+
+    let mut identity = revelio_proof.clone();
+    identity.keyimage_list[2] = PublicKey::new();
+    assert!(identity.verify() == Err(Error::IdentityKeyImage(2)));
+    identity.anon_list[2] = PublicKey::new();
+    assert!(identity.verify() == Err(Error::IdentityOutput(2)));
+
+    let anon_list = revelio_proof.anon_list;
+    let repeated_list = vec![anon_list[0], anon_list[1], anon_list[0]];
+    assert!(RevelioGrinExchange::from_outputs(repeated_list, vec![], DecoySeed::random(&mut rng), &mut rng).err() ==
+            Some(Error::DuplicateOutput { first: 0, second: 2 }));
+
+    let mut grin_exch = SimpleGrinExchange::new(3, &mut rng).unwrap();
+    let mut simple_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    simple_proof.own_list[2] = simple_proof.own_list[0];
+    assert!(simple_proof.verify() == Err(Error::DuplicateOutput { first: 0, second: 2 }));
+  }
+
+  #[test]
+  fn injected_rng_and_deterministic_nonces() {
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut grin_exch = RevelioGrinExchange::new(6, 2, &mut rng).unwrap();
+
+    // The same RNG state reproduces the same proof
+    let proof1 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(12)).unwrap();
+    let proof2 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(12)).unwrap();
+    let proof3 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(13)).unwrap();
+    assert!(ser::serialize(&proof1) == ser::serialize(&proof2));
+    assert!(ser::serialize(&proof1) != ser::serialize(&proof3));
+
+    // Deterministic nonces ignore the RNG
+    grin_exch.set_nonce_mode(NonceMode::Deterministic);
+    let proof4 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(12)).unwrap();
+    let proof5 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(13)).unwrap();
+    assert!(proof4.verify().is_ok());
+    assert!(ser::serialize(&proof4) == ser::serialize(&proof5));
+    assert!(ser::serialize(&proof4) != ser::serialize(&proof1));
+
+    let mut grin_exch = SimpleGrinExchange::new(3, &mut rng).unwrap();
+    grin_exch.set_nonce_mode(NonceMode::Deterministic);
+    let proof6 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(12)).unwrap();
+    let proof7 = grin_exch.generate_proof(&mut StdRng::seed_from_u64(13)).unwrap();
+    assert!(proof6.verify().is_ok());
+    assert!(ser::serialize(&proof6) == ser::serialize(&proof7));
+  }
+
+  #[test]
+  fn thread_count_does_not_change_proofs() {
+    let mut rng = StdRng::seed_from_u64(14);
+    let mut grin_exch = RevelioGrinExchange::new(40, 5, &mut rng).unwrap();
+
+    let mut proofs = vec![];
+    for num_threads in [1, 4] {
+      parallel::set_num_threads(num_threads).unwrap();
+      let proof = grin_exch.generate_proof(&mut StdRng::seed_from_u64(15)).unwrap();
+      assert!(proof.verify().is_ok());
+      proofs.push(ser::serialize(&proof));
+    }
+    parallel::set_num_threads(0).unwrap();
+    assert!(proofs[0] == proofs[1]);
+  }
+
+  #[test]
+  fn batch_verification_locates_failures() {
+    let mut rng = StdRng::seed_from_u64(16);
+    let mut grin_exch = RevelioGrinExchange::new(8, 3, &mut rng).unwrap();
+    let challenge_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    grin_exch.set_encoding(PoKEncoding::Commitment);
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(revelio_proof.verify().is_ok());
+
+    // An output proof from another statement fails the batch and is located
+    grin_exch.set_context(ProofContext { exchange_id: "other".to_string(), block_height: 1 });
+    let other_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    let mut moved = revelio_proof.clone();
+    moved.spk_list[5] = other_proof.spk_list[5].clone();
+    assert!(moved.verify() == Err(Error::InvalidOutputProof(5)));
+
+    // A proof read in the challenge form can be converted to the commitment form
+    let mut converted: RevelioProof = ser::deserialize(&ser::serialize(&challenge_proof)).unwrap();
+    assert!(converted.spk_list.iter().all(|rspk| rspk.commitments().is_none()));
+    converted.set_encoding(PoKEncoding::Commitment).unwrap();
+    assert!(converted.verify().is_ok());
+    assert!(ser::serialize(&converted).len() == ser::serialize(&challenge_proof).len() + 8*3*33);
+  }
+
+  #[test]
+  fn resume_from_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proof.checkpoint");
+    let mut grin_exch = RevelioGrinExchange::new(40, 5, &mut StdRng::seed_from_u64(21)).unwrap();
+    let expected = ser::serialize(&grin_exch.generate_proof(&mut StdRng::seed_from_u64(22)).unwrap());
+
+    // A temporary file left behind by an earlier crash is not reused
+    let tmp_path = dir.path().join("proof.checkpoint.tmp");
+    fs::write(&tmp_path, b"stale").unwrap();
+    let proof = grin_exch.generate_proof_with_checkpoint(&path, &mut StdRng::seed_from_u64(22)).unwrap();
+    assert!(ser::serialize(&proof) == expected);
+    assert!(!tmp_path.exists());
+
+    // Crash in the middle of writing the output proof at index 17
+    let record_size = 5*32 + 3*33;
+    let file_size = fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(file_size - 22*record_size - record_size/2).unwrap();
+
+    // A restarted exchange with the same keys ends up with the same proof
+    // whatever its RNG
+    let mut grin_exch = RevelioGrinExchange::new(40, 5, &mut StdRng::seed_from_u64(21)).unwrap();
+    let proof = grin_exch.generate_proof_with_checkpoint(&path, &mut StdRng::seed_from_u64(23)).unwrap();
+    assert!(proof.verify().is_ok());
+    assert!(ser::serialize(&proof) == expected);
+    assert!(fs::metadata(&path).unwrap().len() == file_size);
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert!(fs::metadata(&path).unwrap().permissions().mode() & 0o777 == 0o600);
+
+      // A checkpoint others can read is refused, as its seed may have leaked
+      fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+      assert!(grin_exch.generate_proof_with_checkpoint(&path, &mut StdRng::seed_from_u64(23)).err() ==
+              Some(Error::CheckpointPermissions(0o644)));
+      fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    // An output proof altered on disk is caught before it enters the proof
+    let mut bytes = fs::read(&path).unwrap();
+    let header_size = bytes.len() - 40*record_size as usize;
+    bytes[header_size + 3*record_size as usize + 31] ^= 1;
+    fs::write(&path, &bytes).unwrap();
+    assert!(grin_exch.generate_proof_with_checkpoint(&path, &mut StdRng::seed_from_u64(23)).err() ==
+            Some(Error::InvalidOutputProof(3)));
+
+    // The checkpoint does not carry over to another statement
+    grin_exch.set_context(ProofContext { exchange_id: "other".to_string(), block_height: 1 });
+    assert!(grin_exch.generate_proof_with_checkpoint(&path, &mut StdRng::seed_from_u64(22)).err() ==
+            Some(Error::CheckpointMismatch));
+  }
+
+  #[test]
+  fn decoy_keyimages_stable_across_epochs() {
+    let mut rng = StdRng::seed_from_u64(25);
+    let secp_inst = secp_context();
+    let mut outputs = vec![];
+    for _i in 0..12 {
+      let sk = SecretKey::new(secp_inst, &mut rng);
+      outputs.push(PublicKey::from_secret_key(secp_inst, &sk).unwrap());
+    }
+    let blinding = SecretKey::new(secp_inst, &mut rng);
+    let own_output = Secp256k1::commit(secp_inst, 5, blinding.clone()).unwrap()
+                       .to_pubkey(secp_inst).unwrap();
+    outputs.push(own_output);
+
+    // Epoch 1 covers outputs 0..10 and epoch 2 outputs 4..13, both of them
+    // holding the own output
+    let prove = |anon_list: &[PublicKey], seed: &DecoySeed, height: u64, rng: &mut StdRng| {
+      let own_outputs = vec![(own_output, blinding.clone(), 5)];
+      let mut grin_exch = RevelioGrinExchange::from_outputs(anon_list.to_vec(), own_outputs, seed.clone(), rng).unwrap();
+      grin_exch.set_context(ProofContext { exchange_id: "exch".to_string(), block_height: height });
+      let proof = grin_exch.generate_proof(rng).unwrap();
+      assert!(proof.verify().is_ok());
+      proof
+    };
+    let common_keyimages = |proof1: &RevelioProof, proof2: &RevelioProof| {
+      let mut common = vec![];
+      for (i, output) in proof1.anon_list.iter().enumerate() {
+        if let Some(j) = proof2.anon_list.iter().position(|c| c == output) {
+          if proof1.keyimage_list[i] == proof2.keyimage_list[j] {
+            common.push(*output);
+          }
+        }
+      }
+      common
+    };
+
+    // With a persistent seed every output in both lists keeps its key image
+    let seed = DecoySeed::random(&mut rng);
+    let proof1 = prove(&outputs[0..10], &seed, 100, &mut rng);
+    let proof2 = prove(&outputs[4..13], &seed, 200, &mut rng);
+    let common = common_keyimages(&proof1, &proof2);
+    assert!(common.len() == 7);
+    assert!(common.iter().all(|c| outputs[4..10].contains(c) || *c == own_output));
+
+    // A fresh seed would single out the own output
+    let proof3 = prove(&outputs[4..13], &DecoySeed::random(&mut rng), 200, &mut rng);
+    assert!(common_keyimages(&proof1, &proof3) == vec![own_output]);
+  }
 }
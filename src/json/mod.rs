@@ -0,0 +1,249 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use secp256k1zkp as secp;
+use secp::key::{SecretKey, PublicKey};
+
+use super::context::secp_context;
+use super::ser::{self, Reader, Writer, PROTOCOL_VERSION};
+
+/// Value of the proof_type field for a SimpleProof
+pub const SIMPLE_PROOF_TYPE: &str = "simple";
+
+/// Value of the proof_type field for a RevelioProof
+pub const REVELIO_PROOF_TYPE: &str = "revelio";
+
+/// Errors encountered while decoding a proof from JSON
+#[derive(Debug)]
+pub enum Error {
+  /// The text is not valid JSON or does not have the expected fields
+  Json(serde_json::Error),
+  /// A point or scalar is not valid hex
+  Hex(hex::FromHexError),
+  /// A decoded point or scalar is malformed
+  Ser(ser::Error),
+  /// The metadata does not match the proof contents
+  Metadata(String),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::Json(ref e) => write!(f, "invalid JSON: {}", e),
+      Error::Hex(ref e) => write!(f, "invalid hex: {}", e),
+      Error::Ser(ref e) => write!(f, "{}", e),
+      Error::Metadata(ref msg) => write!(f, "inconsistent metadata: {}", msg),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_json::Error> for Error {
+  fn from(e: serde_json::Error) -> Error {
+    Error::Json(e)
+  }
+}
+
+impl From<hex::FromHexError> for Error {
+  fn from(e: hex::FromHexError) -> Error {
+    Error::Hex(e)
+  }
+}
+
+impl From<ser::Error> for Error {
+  fn from(e: ser::Error) -> Error {
+    Error::Ser(e)
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RevelioPoKJson {
+  pub c1: String,
+  pub c2: String,
+  pub s1: String,
+  pub s2: String,
+  pub s3: String,
+  pub commitments: Option<RevelioCommitmentsJson>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RevelioCommitmentsJson {
+  pub v1: String,
+  pub v2: String,
+  pub v3: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepresentationPoKJson {
+  pub c: String,
+  pub s1: String,
+  pub s2: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DiscreteLogPoKJson {
+  pub c: String,
+  pub s: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SimpleProofJson {
+  pub version: u8,
+  pub proof_type: String,
+  pub own_list_size: usize,
+  pub blinding_basepoint: String,
+  pub value_basepoint: String,
+  pub total_amount: u64,
+  pub reserve_spk: DiscreteLogPoKJson,
+  pub own_list: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RevelioProofJson {
+  pub version: u8,
+  pub proof_type: String,
+  pub anon_list_size: usize,
+  pub blinding_basepoint: String,
+  pub value_basepoint: String,
+  pub keyimage_basepoint: String,
+  pub exchange_id: String,
+  pub block_height: u64,
+  pub nonce: Option<String>,
+  pub total_amount: u64,
+  pub reserve_spk: DiscreteLogPoKJson,
+  pub anon_list: Vec<String>,
+  pub keyimage_list: Vec<String>,
+  pub spk_encoding: String,
+  pub spk_list: Vec<RevelioPoKJson>,
+}
+
+/// Checks the version and proof type fields
+pub fn check_header(version: u8, proof_type: &str, expected_type: &str) -> Result<(), Error> {
+  if version != PROTOCOL_VERSION {
+    return Err(Error::Ser(ser::Error::UnsupportedVersion(version)));
+  }
+  if proof_type != expected_type {
+    return Err(Error::Metadata(format!("proof type is {}, expected {}", proof_type, expected_type)));
+  }
+  Ok(())
+}
+
+/// Checks that a list has the size declared in the metadata
+pub fn check_list_size(name: &str, declared: usize, actual: usize) -> Result<(), Error> {
+  if declared != actual {
+    return Err(Error::Metadata(format!("{} has {} entries, expected {}", name, actual, declared)));
+  }
+  Ok(())
+}
+
+/// Encodes a point as the hex of its compressed form
+pub fn point_to_hex(point: &PublicKey) -> String {
+  hex::encode(point.serialize_vec(secp_context(), true))
+}
+
+/// Decodes a point with the same checks as the binary format
+pub fn point_from_hex(s: &str) -> Result<PublicKey, Error> {
+  let bytes = hex::decode(s)?;
+  let mut reader = Reader::new(&bytes);
+  let point = reader.read_point()?;
+  reader.finish()?;
+  Ok(point)
+}
+
+pub fn point_list_to_hex(list: &[PublicKey]) -> Vec<String> {
+  list.iter().map(point_to_hex).collect()
+}
+
+pub fn point_list_from_hex(list: &[String]) -> Result<Vec<PublicKey>, Error> {
+  list.iter().map(|s| point_from_hex(s)).collect()
+}
+
+/// Encodes a scalar as the hex of its 32 big-endian bytes
+pub fn scalar_to_hex(scalar: &SecretKey) -> String {
+  let mut writer = Writer::new();
+  writer.write_scalar(scalar);
+  hex::encode(writer.into_bytes())
+}
+
+/// Decodes a scalar with the same checks as the binary format
+pub fn scalar_from_hex(s: &str) -> Result<SecretKey, Error> {
+  let bytes = hex::decode(s)?;
+  let mut reader = Reader::new(&bytes);
+  let scalar = reader.read_scalar()?;
+  reader.finish()?;
+  Ok(scalar)
+}
+
+#[cfg(test)]
+mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+  use super::Error;
+  use super::super::nizk::PoKEncoding;
+  use super::super::ser::{self, serialize, deserialize};
+  use super::super::exchange::{RevelioGrinExchange, SimpleGrinExchange, RevelioProof, SimpleProof};
+
+  #[test]
+  fn json_binary_roundtrip() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut grin_exch = RevelioGrinExchange::new(5, 2, &mut rng).unwrap();
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    let bytes = serialize(&revelio_proof);
+
+    let json = revelio_proof.to_json();
+    let decoded = RevelioProof::from_json(&json).unwrap();
+    assert!(serialize(&decoded) == bytes);
+    assert!(decoded.to_json() == json);
+    assert!(deserialize::<RevelioProof>(&bytes).unwrap().to_json() == json);
+    assert!(decoded.verify().is_ok());
+
+    grin_exch.set_encoding(PoKEncoding::Commitment);
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    let json = revelio_proof.to_json();
+    let decoded = RevelioProof::from_json(&json).unwrap();
+    assert!(serialize(&decoded) == serialize(&revelio_proof));
+    assert!(decoded.verify().is_ok());
+
+    let mut grin_exch = SimpleGrinExchange::new(3, &mut rng).unwrap();
+    let simple_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    let json = simple_proof.to_json();
+    let decoded = SimpleProof::from_json(&json).unwrap();
+    assert!(serialize(&decoded) == serialize(&simple_proof));
+    assert!(decoded.verify().is_ok());
+  }
+
+  #[test]
+  fn reject_malformed_json() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let mut grin_exch = RevelioGrinExchange::new(3, 1, &mut rng).unwrap();
+    let json = grin_exch.generate_proof(&mut rng).unwrap().to_json();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+    let mut bad_size = value.clone();
+    bad_size["anon_list_size"] = serde_json::json!(4);
+    assert!(matches!(RevelioProof::from_json(&bad_size.to_string()), Err(Error::Metadata(_))));
+
+    let mut bad_type = value.clone();
+    bad_type["proof_type"] = serde_json::json!("simple");
+    assert!(matches!(RevelioProof::from_json(&bad_type.to_string()), Err(Error::Metadata(_))));
+
+    let mut bad_hex = value.clone();
+    bad_hex["anon_list"][0] = serde_json::json!("zz");
+    assert!(matches!(RevelioProof::from_json(&bad_hex.to_string()), Err(Error::Hex(_))));
+
+    let mut bad_encoding = value.clone();
+    bad_encoding["spk_encoding"] = serde_json::json!("commitment");
+    assert!(matches!(RevelioProof::from_json(&bad_encoding.to_string()), Err(Error::Metadata(_))));
+
+    let mut bad_scalar = value;
+    bad_scalar["reserve_spk"]["c"] = serde_json::json!("ff".repeat(32));
+    assert!(matches!(RevelioProof::from_json(&bad_scalar.to_string()),
+                     Err(Error::Ser(ser::Error::NonCanonicalScalar))));
+  }
+}
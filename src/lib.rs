@@ -1,2 +1,16 @@
+pub mod audit;
+pub mod checkpoint;
+pub mod context;
+pub mod error;
 pub mod exchange;
+pub mod json;
+mod msm;
 pub mod nizk;
+pub mod parallel;
+#[cfg(feature = "registry")]
+pub mod registry;
+pub mod ser;
+pub mod stream;
+pub mod transcript;
+
+pub use error::Error;
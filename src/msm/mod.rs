@@ -0,0 +1,116 @@
+use k256::{AffinePoint, EncodedPoint, ProjectivePoint, Scalar};
+use k256::elliptic_curve::PrimeField;
+use k256::elliptic_curve::sec1::FromEncodedPoint;
+use secp256k1zkp as secp;
+use secp::Secp256k1;
+use secp::key::{SecretKey, PublicKey};
+
+use super::error::Error;
+use super::parallel;
+
+/// Converts a point to the representation the multi-scalar multiplication
+/// works on
+pub(crate) fn to_affine(secp_inst: &Secp256k1, point: &PublicKey) -> Result<AffinePoint, Error> {
+  let encoded = EncodedPoint::from_bytes(point.serialize_vec(secp_inst, false))
+                  .map_err(|_| Error::Secp(secp::Error::InvalidPublicKey))?;
+  Option::from(AffinePoint::from_encoded_point(&encoded))
+    .ok_or(Error::Secp(secp::Error::InvalidPublicKey))
+}
+
+/// Converts a scalar, which is stored big-endian in a SecretKey
+pub(crate) fn to_scalar(scalar: &SecretKey) -> Result<Scalar, Error> {
+  Option::from(Scalar::from_repr(scalar.0.into()))
+    .ok_or(Error::Secp(secp::Error::InvalidSecretKey))
+}
+
+/// Computes sum(scalars[i]*points[i]) with Pippenger's bucket method.
+///
+/// The scalars are cut into windows of c bits. For every window the points
+/// are sorted into 2^c - 1 buckets by their digit in that window and the
+/// buckets are summed with weights 1..2^c - 1 using additions only. The
+/// window sums are then combined with c doublings per window. For n points
+/// this takes about (256/c)*(n + 2^c) additions instead of n scalar
+/// multiplications, with c close to ln(n). The windows are processed in
+/// parallel with the parallel feature.
+pub(crate) fn multiexp(scalars: &[Scalar], points: &[AffinePoint]) -> ProjectivePoint {
+  assert!(scalars.len() == points.len());
+  let n = points.len();
+  let c = if n < 32 { 3 } else { ((n as f64).ln().ceil() as usize).min(16) };
+  let num_windows = 256_usize.div_ceil(c);
+  let digits: Vec<[u8; 32]> = scalars.iter().map(|s| s.to_bytes().into()).collect();
+
+  let window_sums = parallel::map_indices(num_windows, |w| {
+    let mut buckets = vec![ProjectivePoint::IDENTITY; (1 << c) - 1];
+    for (scalar, point) in digits.iter().zip(points) {
+      let digit = window_digit(scalar, w * c, c);
+      if digit != 0 {
+        buckets[digit - 1] += point;
+      }
+    }
+
+    // sum(d*bucket_d) as a sum of running sums from the top bucket down
+    let mut running = ProjectivePoint::IDENTITY;
+    let mut sum = ProjectivePoint::IDENTITY;
+    for bucket in buckets.iter().rev() {
+      running += bucket;
+      sum += running;
+    }
+    sum
+  });
+
+  let mut result = ProjectivePoint::IDENTITY;
+  for sum in window_sums.iter().rev() {
+    for _i in 0..c {
+      result = result.double();
+    }
+    result += sum;
+  }
+  result
+}
+
+/// Returns the c bits of a big-endian scalar starting at bit `start`,
+/// counted from the least significant bit
+fn window_digit(scalar: &[u8; 32], start: usize, c: usize) -> usize {
+  let mut digit = 0;
+  for k in 0..c {
+    let bit = start + k;
+    if bit >= 256 {
+      break;
+    }
+    let byte = scalar[31 - bit / 8];
+    digit |= (((byte >> (bit % 8)) & 1) as usize) << k;
+  }
+  digit
+}
+
+#[cfg(test)]
+mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+  use secp256k1zkp as secp;
+  use secp::key::{SecretKey, PublicKey};
+  use super::{multiexp, to_affine, to_scalar};
+  use crate::context::secp_context;
+
+  #[test]
+  fn multiexp_matches_scalar_multiplications() {
+    let mut rng = StdRng::seed_from_u64(30);
+    let secp_inst = secp_context();
+
+    for n in [1, 5, 100] {
+      let mut expected = vec![];
+      let mut scalars = vec![];
+      let mut points = vec![];
+      for _i in 0..n {
+        let sk = SecretKey::new(secp_inst, &mut rng);
+        let mut point = PublicKey::from_secret_key(secp_inst, &SecretKey::new(secp_inst, &mut rng)).unwrap();
+        points.push(to_affine(secp_inst, &point).unwrap());
+        scalars.push(to_scalar(&sk).unwrap());
+        point.mul_assign(secp_inst, &sk).unwrap();
+        expected.push(point);
+      }
+      let expected = PublicKey::from_combination(secp_inst, expected.iter().collect()).unwrap();
+      assert!(multiexp(&scalars, &points).to_affine() == to_affine(secp_inst, &expected).unwrap());
+    }
+  }
+}
@@ -1,9 +1,18 @@
-use digest::Digest;
-use sha2::Sha256;
-use rand::thread_rng;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use rand::{RngCore, CryptoRng};
 use secp256k1zkp as secp;
 use secp::Secp256k1;
 use secp::key::{SecretKey, PublicKey, ZERO_KEY};
+use k256::{AffinePoint, ProjectivePoint, Scalar};
+
+use super::error::Error;
+use super::json::{self, RevelioPoKJson, RepresentationPoKJson, DiscreteLogPoKJson};
+use super::json::{RevelioCommitmentsJson, scalar_to_hex, scalar_from_hex, point_to_hex, point_from_hex};
+use super::ser::{self, Readable, Reader, Writeable, Writer};
+use super::msm;
+use super::parallel;
+use super::transcript::{Transcript, NonceMode};
 
 /// The number curve_order-1 encoded as a secret key
 pub const MINUS_ONE_KEY: SecretKey = SecretKey([
@@ -13,13 +22,94 @@ pub const MINUS_ONE_KEY: SecretKey = SecretKey([
     0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x40
 ]);
 
-#[derive(Copy, Clone)]
+/// Proof names which start the transcripts of the sigma proofs
+const REVELIO_POK_NAME: &[u8] = b"RevelioPoK";
+const REPRESENTATION_POK_NAME: &[u8] = b"RepresentationPoK";
+const DLOG_POK_NAME: &[u8] = b"DiscreteLogPoK";
+
+/// Name which starts the transcript the batch verification weights are derived from
+const REVELIO_BATCH_NAME: &[u8] = b"RevelioPoKBatch";
+
+/// How the output proofs of a RevelioProof are encoded
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum PoKEncoding {
+  /// The challenges and responses c_1, c_2, s_1, s_2, s_3 only
+  #[default]
+  Challenge,
+  /// The commitments V_1, V_2, V_3 as well, which allows verifying all
+  /// output proofs at once with batch_verify_spk
+  Commitment,
+}
+
+impl PoKEncoding {
+  /// Value of the encoding in the binary format
+  pub fn to_u8(self) -> u8 {
+    match self {
+      PoKEncoding::Challenge => 0,
+      PoKEncoding::Commitment => 1,
+    }
+  }
+
+  pub fn from_u8(flag: u8) -> Result<PoKEncoding, ser::Error> {
+    match flag {
+      0 => Ok(PoKEncoding::Challenge),
+      1 => Ok(PoKEncoding::Commitment),
+      flag => Err(ser::Error::InvalidFlag(flag)),
+    }
+  }
+
+  /// Value of the encoding in the JSON format
+  pub fn as_str(self) -> &'static str {
+    match self {
+      PoKEncoding::Challenge => "challenge",
+      PoKEncoding::Commitment => "commitment",
+    }
+  }
+}
+
+impl FromStr for PoKEncoding {
+  type Err = json::Error;
+
+  fn from_str(s: &str) -> Result<PoKEncoding, json::Error> {
+    match s {
+      "challenge" => Ok(PoKEncoding::Challenge),
+      "commitment" => Ok(PoKEncoding::Commitment),
+      _ => Err(json::Error::Metadata(format!("unknown proof encoding {}", s))),
+    }
+  }
+}
+
+/// The commitments of a RevelioPoK
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RevelioCommitments {
+  pub v1: PublicKey, // V_1 = s_1*G + s_2*H + c_1*C_i
+  pub v2: PublicKey, // V_2 = s_1*G' + s_2*H + c_1*I_i
+  pub v3: PublicKey, // V_3 = s_3*G' + c_2*I_i
+}
+
+#[derive(Clone)]
 pub struct RevelioPoK {
   c1: SecretKey,
   c2: SecretKey,
   s1: SecretKey,
   s2: SecretKey,
   s3: SecretKey,
+  commitments: Option<RevelioCommitments>,
+}
+
+/// Contribution of one output proof to the batch verification equation
+struct BatchTerms {
+  g_scalar: Scalar,           // w_1*s_1
+  h_scalar: Scalar,           // (w_1 + w_2)*s_2
+  gp_scalar: Scalar,          // w_2*s_1 + w_3*s_3
+  scalars: [Scalar; 5],       // w_1*c_1, w_2*c_1 + w_3*c_2, -w_1, -w_2, -w_3
+  points: [AffinePoint; 5],   // C_i, I_i, V_1, V_2, V_3
+}
+
+impl Default for RevelioPoK {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl RevelioPoK {
@@ -30,76 +120,93 @@ impl RevelioPoK {
       s1: ZERO_KEY,
       s2: ZERO_KEY,
       s3: ZERO_KEY,
+      commitments: None,
     }
   }
 
+  /// Returns the commitments V_1, V_2, V_3 if the proof carries them
+  pub fn commitments(&self) -> Option<RevelioCommitments> {
+    self.commitments
+  }
+
+  #[allow(clippy::too_many_arguments)]
   pub fn create_spk_from_decoykey (
+    secp_inst: &Secp256k1,
     output: PublicKey,
     keyimage: PublicKey,
     dkey: SecretKey,
     blinding_gen: PublicKey, // G
     value_gen: PublicKey,    // H
     keyimage_gen: PublicKey, // G'
-  ) -> RevelioPoK {
-    let mut rng = thread_rng();
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-
+    statement: &[u8],        // digest of the whole statement
+    nonce: Option<&[u8]>,    // verifier nonce
+    mode: NonceMode,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioPoK, Error> {
+    // Transcript of the statement S, completed with V_1, V_2, V_3 below
+    let mut transcript = Transcript::new(secp_inst, REVELIO_POK_NAME);
+    transcript.append_message(b"statement", statement);     // Hash statement digest
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"G", &blinding_gen);           // Hash G
+    transcript.append_point(b"G'", &keyimage_gen);          // Hash G'
+    transcript.append_point(b"H", &value_gen);              // Hash H
+    transcript.append_point(b"C_i", &output);               // Hash C_i
+    transcript.append_point(b"I_i", &keyimage);             // Hash I_i
+
+    let nonces = transcript.nonce_generator(&[&dkey], mode, rng);
     let mut rspk = RevelioPoK::new();
-    let r3 = SecretKey::new(&secp_inst, &mut rng);
-    rspk.c1 = SecretKey::new(&secp_inst, &mut rng);
-    rspk.s1 = SecretKey::new(&secp_inst, &mut rng);
-    rspk.s2 = SecretKey::new(&secp_inst, &mut rng);
+    let r3 = nonces.scalar(b"r_3");
+    rspk.c1 = nonces.scalar(b"c_1");
+    rspk.s1 = nonces.scalar(b"s_1");
+    rspk.s2 = nonces.scalar(b"s_2");
 
     // Calculation of V_1 = s_1*G + s_2*H + c_1*X    where X = C_i
-    let s1_g = PublicKey::from_secret_key(&secp_inst, &rspk.s1).unwrap();
-    let mut s2_h = value_gen.clone();
-    s2_h.mul_assign(&secp_inst, &rspk.s2).unwrap();
-    let mut c1_x = output.clone();
-    c1_x.mul_assign(&secp_inst, &rspk.c1).unwrap();
-    let v1 = PublicKey::from_combination(&secp_inst, vec![&s1_g, &s2_h, &c1_x]).unwrap();
+    let s1_g = PublicKey::from_secret_key(secp_inst, &rspk.s1)?;
+    let mut s2_h = value_gen;
+    s2_h.mul_assign(secp_inst, &rspk.s2)?;
+    let mut c1_x = output;
+    c1_x.mul_assign(secp_inst, &rspk.c1)?;
+    let v1 = PublicKey::from_combination(secp_inst, vec![&s1_g, &s2_h, &c1_x])?;
 
     // Calculation of V_2 = s_1*G' + s_2*H + c_1*Y   where Y = I_i
-    let mut s1_gp = keyimage_gen.clone();
-    s1_gp.mul_assign(&secp_inst, &rspk.s1).unwrap();
-    let mut c1_y = keyimage.clone();
-    c1_y.mul_assign(&secp_inst, &rspk.c1).unwrap();
-    let v2 = PublicKey::from_combination(&secp_inst, vec![&s1_gp, &s2_h, &c1_y]).unwrap();
+    let mut s1_gp = keyimage_gen;
+    s1_gp.mul_assign(secp_inst, &rspk.s1)?;
+    let mut c1_y = keyimage;
+    c1_y.mul_assign(secp_inst, &rspk.c1)?;
+    let v2 = PublicKey::from_combination(secp_inst, vec![&s1_gp, &s2_h, &c1_y])?;
 
     // Calculation of r_3*G'
-    let mut r3_gp = keyimage_gen.clone();
-    r3_gp.mul_assign(&secp_inst, &r3).unwrap();
+    let mut r3_gp = keyimage_gen;
+    r3_gp.mul_assign(secp_inst, &r3)?;
 
     // Calculation of H(S || V_1 || V_2 || r_3*G')
-    let mut hasher = Sha256::new();
-    hasher.input(blinding_gen.serialize_vec(&secp_inst, true)); // Hash G
-    hasher.input(keyimage_gen.serialize_vec(&secp_inst, true)); // Hash G'
-    hasher.input(value_gen.serialize_vec(&secp_inst, true));    // Hash H
-    hasher.input(output.serialize_vec(&secp_inst, true));       // Hash C_i
-    hasher.input(keyimage.serialize_vec(&secp_inst, true));     // Hash I_i
-    hasher.input(v1.serialize_vec(&secp_inst, true));           // Hash V_1
-    hasher.input(v2.serialize_vec(&secp_inst, true));           // Hash V_2
-    hasher.input(r3_gp.serialize_vec(&secp_inst, true));        // Hash r_3*G'
-
-    let hash_scalar = SecretKey::from_slice(&secp_inst, &hasher.result()).unwrap();
+    transcript.append_point(b"V_1", &v1);                   // Hash V_1
+    transcript.append_point(b"V_2", &v2);                   // Hash V_2
+    transcript.append_point(b"V_3", &r3_gp);                // Hash r_3*G'
+
+    let hash_scalar = transcript.challenge_scalar(b"c");
+    rspk.commitments = Some(RevelioCommitments { v1, v2, v3: r3_gp });
 
     // Calculation of -c_1
-    let mut minus_c1 = rspk.c1;
-    minus_c1.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();
+    let mut minus_c1 = rspk.c1.clone();
+    minus_c1.mul_assign(secp_inst, &MINUS_ONE_KEY)?;
 
     // Calculation of c_2
     rspk.c2 = hash_scalar;                                      // c_2 = H(S...r_3*G')
-    rspk.c2.add_assign(&secp_inst, &minus_c1).unwrap();         // c_2 = H(S...r_3*G') - c_1
+    rspk.c2.add_assign(secp_inst, &minus_c1)?;          // c_2 = H(S...r_3*G') - c_1
 
     // Calculation of s_3
     rspk.s3 = dkey;                                             // s_3 = gamma
-    rspk.s3.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();    // s_3 = -gamma
-    rspk.s3.mul_assign(&secp_inst, &rspk.c2).unwrap();          // s_3 = -c_2*gamma
-    rspk.s3.add_assign(&secp_inst, &r3).unwrap();               // s_3 = r_3 - c_2*gamma
+    rspk.s3.mul_assign(secp_inst, &MINUS_ONE_KEY)?;     // s_3 = -gamma
+    rspk.s3.mul_assign(secp_inst, &rspk.c2)?;           // s_3 = -c_2*gamma
+    rspk.s3.add_assign(secp_inst, &r3)?;                // s_3 = r_3 - c_2*gamma
 
-    rspk
+    Ok(rspk)
   }
 
+  #[allow(clippy::too_many_arguments)]
   pub fn create_spk_from_representation (
+    secp_inst: &Secp256k1,
     output: PublicKey,
     keyimage: PublicKey,
     blinding_factor: SecretKey,
@@ -107,140 +214,472 @@ impl RevelioPoK {
     blinding_gen: PublicKey, // G
     value_gen: PublicKey,    // H
     keyimage_gen: PublicKey, // G'
-  ) -> RevelioPoK {
-    let mut rng = thread_rng();
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-
+    statement: &[u8],        // digest of the whole statement
+    nonce: Option<&[u8]>,    // verifier nonce
+    mode: NonceMode,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RevelioPoK, Error> {
+    let amount_key = RevelioPoK::amount_to_key(secp_inst, amount)?;
+
+    // Transcript of the statement S, completed with V_1, V_2, V_3 below
+    let mut transcript = Transcript::new(secp_inst, REVELIO_POK_NAME);
+    transcript.append_message(b"statement", statement);     // Hash statement digest
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"G", &blinding_gen);           // Hash G
+    transcript.append_point(b"G'", &keyimage_gen);          // Hash G'
+    transcript.append_point(b"H", &value_gen);              // Hash H
+    transcript.append_point(b"C_i", &output);               // Hash C_i
+    transcript.append_point(b"I_i", &keyimage);             // Hash I_i
+
+    let nonces = transcript.nonce_generator(&[&blinding_factor, &amount_key], mode, rng);
     let mut rspk = RevelioPoK::new();
-    let r1 = SecretKey::new(&secp_inst, &mut rng);
-    let r2 = SecretKey::new(&secp_inst, &mut rng);
-    rspk.c2 = SecretKey::new(&secp_inst, &mut rng);
-    rspk.s3 = SecretKey::new(&secp_inst, &mut rng);
+    let r1 = nonces.scalar(b"r_1");
+    let r2 = nonces.scalar(b"r_2");
+    rspk.c2 = nonces.scalar(b"c_2");
+    rspk.s3 = nonces.scalar(b"s_3");
 
     // Calculation of V_3 = s_3*G' + c_2*Y   where Y = I_i
-    let mut s3_gp = keyimage_gen.clone();
-    s3_gp.mul_assign(&secp_inst, &rspk.s3).unwrap();
-    let mut c2_y = keyimage.clone();
-    c2_y.mul_assign(&secp_inst, &rspk.c2).unwrap();
-    let v3 = PublicKey::from_combination(&secp_inst, vec![&s3_gp, &c2_y]).unwrap();
+    let mut s3_gp = keyimage_gen;
+    s3_gp.mul_assign(secp_inst, &rspk.s3)?;
+    let mut c2_y = keyimage;
+    c2_y.mul_assign(secp_inst, &rspk.c2)?;
+    let v3 = PublicKey::from_combination(secp_inst, vec![&s3_gp, &c2_y])?;
 
     // Calculation of r_1*G + r_2*H
-    let r1_g = PublicKey::from_secret_key(&secp_inst, &r1).unwrap();
-    let mut r2_h = value_gen.clone();
-    r2_h.mul_assign(&secp_inst, &r2).unwrap();
-    let r1g_r2h = PublicKey::from_combination(&secp_inst, vec![&r1_g, &r2_h]).unwrap();
+    let r1_g = PublicKey::from_secret_key(secp_inst, &r1)?;
+    let mut r2_h = value_gen;
+    r2_h.mul_assign(secp_inst, &r2)?;
+    let r1g_r2h = PublicKey::from_combination(secp_inst, vec![&r1_g, &r2_h])?;
 
     // Calculation of r_1*G' + r_2*H
-    let mut r1_gp = keyimage_gen.clone();
-    r1_gp.mul_assign(&secp_inst, &r1).unwrap();
-    let r1gp_r2h = PublicKey::from_combination(&secp_inst, vec![&r1_gp, &r2_h]).unwrap();
+    let mut r1_gp = keyimage_gen;
+    r1_gp.mul_assign(secp_inst, &r1)?;
+    let r1gp_r2h = PublicKey::from_combination(secp_inst, vec![&r1_gp, &r2_h])?;
 
     // Calculation of H(S || r_1*G + r_2*H || r_1*G'+r_2*H || V_3)
-    let mut hasher = Sha256::new();
-    hasher.input(blinding_gen.serialize_vec(&secp_inst, true)); // Hash G
-    hasher.input(keyimage_gen.serialize_vec(&secp_inst, true)); // Hash G'
-    hasher.input(value_gen.serialize_vec(&secp_inst, true));    // Hash H
-    hasher.input(output.serialize_vec(&secp_inst, true));       // Hash C_i
-    hasher.input(keyimage.serialize_vec(&secp_inst, true));     // Hash I_i
-    hasher.input(r1g_r2h.serialize_vec(&secp_inst, true));      // Hash r_1*G + r_2*H
-    hasher.input(r1gp_r2h.serialize_vec(&secp_inst, true));     // Hash r_1*G' + r_2*H
-    hasher.input(v3.serialize_vec(&secp_inst, true));           // Hash V_3
-
-    let hash_scalar = SecretKey::from_slice(&secp_inst, &hasher.result()).unwrap();
+    transcript.append_point(b"V_1", &r1g_r2h);              // Hash r_1*G + r_2*H
+    transcript.append_point(b"V_2", &r1gp_r2h);             // Hash r_1*G' + r_2*H
+    transcript.append_point(b"V_3", &v3);                   // Hash V_3
+
+    let hash_scalar = transcript.challenge_scalar(b"c");
+    rspk.commitments = Some(RevelioCommitments { v1: r1g_r2h, v2: r1gp_r2h, v3 });
 
     // Calculation of -c_2
-    let mut minus_c2 = rspk.c2;
-    minus_c2.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();
+    let mut minus_c2 = rspk.c2.clone();
+    minus_c2.mul_assign(secp_inst, &MINUS_ONE_KEY)?;
 
     // Calculation of c_1
     rspk.c1 = hash_scalar;                                      // c_1 = H(S...V_3)
-    rspk.c1.add_assign(&secp_inst, &minus_c2).unwrap();         // c_1 = H(S...V_3) - c_2
+    rspk.c1.add_assign(secp_inst, &minus_c2)?;          // c_1 = H(S...V_3) - c_2
 
     // Calculation of s_1
     rspk.s1 = blinding_factor;                                  // s_1 = alpha
-    rspk.s1.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();    // s_1 = -alpha
-    rspk.s1.mul_assign(&secp_inst, &rspk.c1).unwrap();          // s_1 = -c_1*alpha
-    rspk.s1.add_assign(&secp_inst, &r1).unwrap();               // s_1 = r_1 - c_1*alpha
+    rspk.s1.mul_assign(secp_inst, &MINUS_ONE_KEY)?;     // s_1 = -alpha
+    rspk.s1.mul_assign(secp_inst, &rspk.c1)?;           // s_1 = -c_1*alpha
+    rspk.s1.add_assign(secp_inst, &r1)?;                // s_1 = r_1 - c_1*alpha
 
     // Calculation of s_2
-    rspk.s2 = RevelioPoK::amount_to_key(&secp_inst, amount);                                    // s_2 = beta
-    rspk.s2.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();    // s_2 = -beta
-    rspk.s2.mul_assign(&secp_inst, &rspk.c1).unwrap();          // s_2 = -c_1*beta
-    rspk.s2.add_assign(&secp_inst, &r2).unwrap();               // s_2 = r_2 - c_1*beta
+    rspk.s2 = amount_key;                                       // s_2 = beta
+    rspk.s2.mul_assign(secp_inst, &MINUS_ONE_KEY)?;     // s_2 = -beta
+    rspk.s2.mul_assign(secp_inst, &rspk.c1)?;           // s_2 = -c_1*beta
+    rspk.s2.add_assign(secp_inst, &r2)?;                // s_2 = r_2 - c_1*beta
 
-    rspk
+    Ok(rspk)
   }
 
-  pub fn amount_to_key (secp_inst: &Secp256k1, amount: u64) -> SecretKey {
-    assert!(amount != 0);
+  pub fn amount_to_key (secp_inst: &Secp256k1, amount: u64) -> Result<SecretKey, Error> {
+    if amount == 0u64 {
+      return Err(Error::ZeroAmount);
+    }
     // Converting u64 amount to a scalar i.e. SecretKey
     let amount_as_bytes = amount.to_be_bytes();
     let mut amount_scalar_vec = vec![0u8; 24];
     amount_scalar_vec.extend_from_slice(&amount_as_bytes);
-    let amount_scalar = SecretKey::from_slice(&secp_inst, amount_scalar_vec.as_slice()).unwrap();
+    let amount_scalar = SecretKey::from_slice(secp_inst, amount_scalar_vec.as_slice())?;
 
-    amount_scalar
+    Ok(amount_scalar)
   }
 
-  pub fn verify_spk (
+  /// Recomputes V_1, V_2, V_3 from the challenges and responses
+  fn recompute_commitments(
+    secp_inst: &Secp256k1,
     output: &PublicKey,
     keyimage: &PublicKey,
-    blinding_gen: &PublicKey, // G
     value_gen: &PublicKey,    // H
     keyimage_gen: &PublicKey, // G'
     rspk: &RevelioPoK
-  ) -> bool {
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-
+  ) -> Result<RevelioCommitments, Error> {
     // Calculation of V_1 = s_1*G + s_2*H + c_1*X    where X = C_i
-    let s1_g = PublicKey::from_secret_key(&secp_inst, &rspk.s1).unwrap();
-    let mut s2_h = value_gen.clone();
-    s2_h.mul_assign(&secp_inst, &rspk.s2).unwrap();
-    let mut c1_x = output.clone();
-    c1_x.mul_assign(&secp_inst, &rspk.c1).unwrap();
-    let v1 = PublicKey::from_combination(&secp_inst, vec![&s1_g, &s2_h, &c1_x]).unwrap();
+    let s1_g = PublicKey::from_secret_key(secp_inst, &rspk.s1)?;
+    let mut s2_h = *value_gen;
+    s2_h.mul_assign(secp_inst, &rspk.s2)?;
+    let mut c1_x = *output;
+    c1_x.mul_assign(secp_inst, &rspk.c1)?;
+    let v1 = PublicKey::from_combination(secp_inst, vec![&s1_g, &s2_h, &c1_x])?;
 
     // Calculation of V_2 = s_1*G' + s_2*H + c_1*Y   where Y = I_i
-    let mut s1_gp = keyimage_gen.clone();
-    s1_gp.mul_assign(&secp_inst, &rspk.s1).unwrap();
-    let mut c1_y = keyimage.clone();
-    c1_y.mul_assign(&secp_inst, &rspk.c1).unwrap();
-    let v2 = PublicKey::from_combination(&secp_inst, vec![&s1_gp, &s2_h, &c1_y]).unwrap();
+    let mut s1_gp = *keyimage_gen;
+    s1_gp.mul_assign(secp_inst, &rspk.s1)?;
+    let mut c1_y = *keyimage;
+    c1_y.mul_assign(secp_inst, &rspk.c1)?;
+    let v2 = PublicKey::from_combination(secp_inst, vec![&s1_gp, &s2_h, &c1_y])?;
 
     // Calculation of V_3 = s_3*G' + c_2*Y   where Y = I_i
-    let mut s3_gp = keyimage_gen.clone();
-    s3_gp.mul_assign(&secp_inst, &rspk.s3).unwrap();
-    let mut c2_y = keyimage.clone();
-    c2_y.mul_assign(&secp_inst, &rspk.c2).unwrap();
-    let v3 = PublicKey::from_combination(&secp_inst, vec![&s3_gp, &c2_y]).unwrap();
+    let mut s3_gp = *keyimage_gen;
+    s3_gp.mul_assign(secp_inst, &rspk.s3)?;
+    let mut c2_y = *keyimage;
+    c2_y.mul_assign(secp_inst, &rspk.c2)?;
+    let v3 = PublicKey::from_combination(secp_inst, vec![&s3_gp, &c2_y])?;
+
+    Ok(RevelioCommitments { v1, v2, v3 })
+  }
+
+  /// Calculates H(S || V_1 || V_2 || V_3), which c_1 + c_2 must equal
+  #[allow(clippy::too_many_arguments)]
+  fn challenge(
+    secp_inst: &Secp256k1,
+    output: &PublicKey,
+    keyimage: &PublicKey,
+    blinding_gen: &PublicKey, // G
+    value_gen: &PublicKey,    // H
+    keyimage_gen: &PublicKey, // G'
+    statement: &[u8],         // digest of the whole statement
+    nonce: Option<&[u8]>,     // verifier nonce
+    commitments: &RevelioCommitments,
+  ) -> SecretKey {
+    let mut transcript = Transcript::new(secp_inst, REVELIO_POK_NAME);
+    transcript.append_message(b"statement", statement);     // Hash statement digest
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"G", blinding_gen);            // Hash G
+    transcript.append_point(b"G'", keyimage_gen);           // Hash G'
+    transcript.append_point(b"H", value_gen);               // Hash H
+    transcript.append_point(b"C_i", output);                // Hash C_i
+    transcript.append_point(b"I_i", keyimage);              // Hash I_i
+    transcript.append_point(b"V_1", &commitments.v1);       // Hash V_1
+    transcript.append_point(b"V_2", &commitments.v2);       // Hash V_2
+    transcript.append_point(b"V_3", &commitments.v3);       // Hash V_3
+
+    transcript.challenge_scalar(b"c")
+  }
 
-    // Calculation of H(S || V_1 || V_2 || V_3)
-    let mut hasher = Sha256::new();
-    hasher.input(blinding_gen.serialize_vec(&secp_inst, true)); // Hash G
-    hasher.input(keyimage_gen.serialize_vec(&secp_inst, true)); // Hash G'
-    hasher.input(value_gen.serialize_vec(&secp_inst, true));    // Hash H
-    hasher.input(output.serialize_vec(&secp_inst, true));       // Hash C_i
-    hasher.input(keyimage.serialize_vec(&secp_inst, true));     // Hash I_i
-    hasher.input(v1.serialize_vec(&secp_inst, true));           // Hash V_1
-    hasher.input(v2.serialize_vec(&secp_inst, true));           // Hash V_2
-    hasher.input(v3.serialize_vec(&secp_inst, true));           // Hash V_3
+  /// Returns the proof in the commitment form, recomputing V_1, V_2, V_3 if
+  /// the proof does not carry them
+  pub fn with_commitments(
+    &self,
+    secp_inst: &Secp256k1,
+    output: &PublicKey,
+    keyimage: &PublicKey,
+    value_gen: &PublicKey,    // H
+    keyimage_gen: &PublicKey, // G'
+  ) -> Result<RevelioPoK, Error> {
+    let mut rspk = self.clone();
+    if rspk.commitments.is_none() {
+      rspk.commitments = Some(RevelioPoK::recompute_commitments(
+                                secp_inst,
+                                output,
+                                keyimage,
+                                value_gen,
+                                keyimage_gen,
+                                self,
+                              )?);
+    }
+    Ok(rspk)
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  pub fn verify_spk (
+    secp_inst: &Secp256k1,
+    output: &PublicKey,
+    keyimage: &PublicKey,
+    blinding_gen: &PublicKey, // G
+    value_gen: &PublicKey,    // H
+    keyimage_gen: &PublicKey, // G'
+    statement: &[u8],         // digest of the whole statement
+    nonce: Option<&[u8]>,     // verifier nonce
+    rspk: &RevelioPoK
+  ) -> Result<bool, Error> {
+    let commitments = RevelioPoK::recompute_commitments(
+                        secp_inst,
+                        output,
+                        keyimage,
+                        value_gen,
+                        keyimage_gen,
+                        rspk,
+                      )?;
+
+    // Commitments sent along with the proof must be the recomputed ones
+    if let Some(sent) = rspk.commitments {
+      if sent != commitments {
+        return Ok(false);
+      }
+    }
 
-    let hash_scalar = SecretKey::from_slice(&secp_inst, &hasher.result()).unwrap();
+    let hash_scalar = RevelioPoK::challenge(
+                        secp_inst,
+                        output,
+                        keyimage,
+                        blinding_gen,
+                        value_gen,
+                        keyimage_gen,
+                        statement,
+                        nonce,
+                        &commitments,
+                      );
+
+    let mut c_sum = rspk.c1.clone();
+    c_sum.add_assign(secp_inst, &rspk.c2)?;
+
+    Ok(c_sum == hash_scalar)
+  }
+
+  /// Verifies the proofs of all outputs of a statement at once.
+  ///
+  /// Every proof must be in the commitment form. The three verification
+  /// equations of output i are multiplied by weights w_1, w_2, w_3 and all of
+  /// them are added up into a single multi-scalar multiplication over the
+  /// 5n + 3 points, which must be the point at infinity:
+  ///
+  /// ```text
+  /// sum(w_1*s_1)*G + sum((w_1 + w_2)*s_2)*H + sum(w_2*s_1 + w_3*s_3)*G'
+  ///   + sum(w_1*c_1*C_i + (w_2*c_1 + w_3*c_2)*I_i)
+  ///   - sum(w_1*V_1 + w_2*V_2 + w_3*V_3) = 0
+  /// ```
+  ///
+  /// The weights are derived from a hash of all the proofs, so they are
+  /// fixed only after the proofs are. Each output's challenges must still
+  /// add up to the hash of its commitments, which takes one hash per output
+  /// but no point arithmetic. A false result does not say which proof is
+  /// invalid; verify_spk on each proof finds it.
+  #[allow(clippy::too_many_arguments)]
+  pub fn batch_verify_spk (
+    secp_inst: &Secp256k1,
+    outputs: &[PublicKey],
+    keyimages: &[PublicKey],
+    rspks: &[RevelioPoK],
+    blinding_gen: &PublicKey, // G
+    value_gen: &PublicKey,    // H
+    keyimage_gen: &PublicKey, // G'
+    statement: &[u8],         // digest of the whole statement
+    nonce: Option<&[u8]>,     // verifier nonce
+  ) -> Result<bool, Error> {
+    if outputs.is_empty() {
+      return Err(Error::EmptyList);
+    }
+    if keyimages.len() != outputs.len() {
+      return Err(Error::ListSizeMismatch { expected: outputs.len(), found: keyimages.len() });
+    }
+    if rspks.len() != outputs.len() {
+      return Err(Error::ListSizeMismatch { expected: outputs.len(), found: rspks.len() });
+    }
+    let mut commitments = Vec::with_capacity(rspks.len());
+    for rspk in rspks {
+      match rspk.commitments {
+        Some(ref rspk_commitments) => commitments.push(rspk_commitments),
+        None => return Ok(false),
+      }
+    }
 
-    let mut c_sum = rspk.c1;
-    c_sum.add_assign(&secp_inst, &rspk.c2).unwrap();
+    // Transcript of all the proofs, from which the weights are derived
+    let mut transcript = Transcript::new(secp_inst, REVELIO_BATCH_NAME);
+    transcript.append_message(b"statement", statement);     // Hash statement digest
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    for i in 0..rspks.len() {
+      let rspk = &rspks[i];
+      transcript.append_point(b"C_i", &outputs[i]);         // Hash C_i
+      transcript.append_point(b"I_i", &keyimages[i]);       // Hash I_i
+      transcript.append_point(b"V_1", &commitments[i].v1);  // Hash V_1
+      transcript.append_point(b"V_2", &commitments[i].v2);  // Hash V_2
+      transcript.append_point(b"V_3", &commitments[i].v3);  // Hash V_3
+      transcript.append_message(b"c_1", &rspk.c1.0);        // Hash c_1
+      transcript.append_message(b"c_2", &rspk.c2.0);        // Hash c_2
+      transcript.append_message(b"s_1", &rspk.s1.0);        // Hash s_1
+      transcript.append_message(b"s_2", &rspk.s2.0);        // Hash s_2
+      transcript.append_message(b"s_3", &rspk.s3.0);        // Hash s_3
+    }
+
+    let terms = parallel::map_indices(rspks.len(), |i| -> Result<Option<BatchTerms>, Error> {
+      let rspk = &rspks[i];
+      let commitments = commitments[i];
+
+      // The challenges must add up to the hash of the commitments
+      let hash_scalar = RevelioPoK::challenge(
+                          secp_inst,
+                          &outputs[i],
+                          &keyimages[i],
+                          blinding_gen,
+                          value_gen,
+                          keyimage_gen,
+                          statement,
+                          nonce,
+                          commitments,
+                        );
+      let (c1, c2) = (msm::to_scalar(&rspk.c1)?, msm::to_scalar(&rspk.c2)?);
+      if c1 + c2 != msm::to_scalar(&hash_scalar)? {
+        return Ok(None);
+      }
+
+      let mut item_transcript = transcript.clone();
+      item_transcript.append_message(b"index", &(i as u64).to_be_bytes());
+      let w1 = msm::to_scalar(&item_transcript.clone().challenge_scalar(b"w_1"))?;
+      let w2 = msm::to_scalar(&item_transcript.clone().challenge_scalar(b"w_2"))?;
+      let w3 = msm::to_scalar(&item_transcript.challenge_scalar(b"w_3"))?;
+      let s1 = msm::to_scalar(&rspk.s1)?;
+      let s2 = msm::to_scalar(&rspk.s2)?;
+      let s3 = msm::to_scalar(&rspk.s3)?;
+
+      Ok(Some(BatchTerms {
+        g_scalar: w1 * s1,
+        h_scalar: (w1 + w2) * s2,
+        gp_scalar: w2 * s1 + w3 * s3,
+        scalars: [w1 * c1, w2 * c1 + w3 * c2, -w1, -w2, -w3],
+        points: [
+          msm::to_affine(secp_inst, &outputs[i])?,
+          msm::to_affine(secp_inst, &keyimages[i])?,
+          msm::to_affine(secp_inst, &commitments.v1)?,
+          msm::to_affine(secp_inst, &commitments.v2)?,
+          msm::to_affine(secp_inst, &commitments.v3)?,
+        ],
+      }))
+    });
+
+    let mut g_scalar = Scalar::ZERO;
+    let mut h_scalar = Scalar::ZERO;
+    let mut gp_scalar = Scalar::ZERO;
+    let mut scalars = Vec::with_capacity(5 * rspks.len() + 3);
+    let mut points = Vec::with_capacity(5 * rspks.len() + 3);
+    for term in terms {
+      let term = match term? {
+        Some(term) => term,
+        None => return Ok(false),
+      };
+      g_scalar += term.g_scalar;
+      h_scalar += term.h_scalar;
+      gp_scalar += term.gp_scalar;
+      scalars.extend_from_slice(&term.scalars);
+      points.extend_from_slice(&term.points);
+    }
+    scalars.extend_from_slice(&[g_scalar, h_scalar, gp_scalar]);
+    points.push(msm::to_affine(secp_inst, blinding_gen)?);
+    points.push(msm::to_affine(secp_inst, value_gen)?);
+    points.push(msm::to_affine(secp_inst, keyimage_gen)?);
+
+    Ok(msm::multiexp(&scalars, &points) == ProjectivePoint::IDENTITY)
+  }
+}
+
+impl Writeable for RevelioPoK {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_scalar(&self.c1);
+    writer.write_scalar(&self.c2);
+    writer.write_scalar(&self.s1);
+    writer.write_scalar(&self.s2);
+    writer.write_scalar(&self.s3);
+  }
+}
+
+impl Readable for RevelioPoK {
+  fn read(reader: &mut Reader) -> Result<RevelioPoK, ser::Error> {
+    Ok(RevelioPoK {
+      c1: reader.read_scalar()?,
+      c2: reader.read_scalar()?,
+      s1: reader.read_scalar()?,
+      s2: reader.read_scalar()?,
+      s3: reader.read_scalar()?,
+      commitments: None,
+    })
+  }
+}
+
+impl From<&RevelioPoK> for RevelioPoKJson {
+  fn from(rspk: &RevelioPoK) -> RevelioPoKJson {
+    RevelioPoKJson {
+      c1: scalar_to_hex(&rspk.c1),
+      c2: scalar_to_hex(&rspk.c2),
+      s1: scalar_to_hex(&rspk.s1),
+      s2: scalar_to_hex(&rspk.s2),
+      s3: scalar_to_hex(&rspk.s3),
+      commitments: rspk.commitments.as_ref().map(RevelioCommitmentsJson::from),
+    }
+  }
+}
+
+impl TryFrom<&RevelioPoKJson> for RevelioPoK {
+  type Error = json::Error;
+
+  fn try_from(rspk: &RevelioPoKJson) -> Result<RevelioPoK, json::Error> {
+    Ok(RevelioPoK {
+      c1: scalar_from_hex(&rspk.c1)?,
+      c2: scalar_from_hex(&rspk.c2)?,
+      s1: scalar_from_hex(&rspk.s1)?,
+      s2: scalar_from_hex(&rspk.s2)?,
+      s3: scalar_from_hex(&rspk.s3)?,
+      commitments: rspk.commitments.as_ref().map(RevelioCommitments::try_from).transpose()?,
+    })
+  }
+}
+
+impl RevelioPoK {
+  /// Reads a proof followed by its commitments, as written in the commitment form
+  pub fn read_with_commitments(reader: &mut Reader) -> Result<RevelioPoK, ser::Error> {
+    let mut rspk = RevelioPoK::read(reader)?;
+    rspk.commitments = Some(RevelioCommitments::read(reader)?);
+    Ok(rspk)
+  }
+}
 
-    c_sum == hash_scalar
+impl Writeable for RevelioCommitments {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_point(&self.v1);
+    writer.write_point(&self.v2);
+    writer.write_point(&self.v3);
   }
 }
 
-#[derive(Copy, Clone)]
+impl Readable for RevelioCommitments {
+  fn read(reader: &mut Reader) -> Result<RevelioCommitments, ser::Error> {
+    Ok(RevelioCommitments {
+      v1: reader.read_point()?,
+      v2: reader.read_point()?,
+      v3: reader.read_point()?,
+    })
+  }
+}
+
+impl From<&RevelioCommitments> for RevelioCommitmentsJson {
+  fn from(commitments: &RevelioCommitments) -> RevelioCommitmentsJson {
+    RevelioCommitmentsJson {
+      v1: point_to_hex(&commitments.v1),
+      v2: point_to_hex(&commitments.v2),
+      v3: point_to_hex(&commitments.v3),
+    }
+  }
+}
+
+impl TryFrom<&RevelioCommitmentsJson> for RevelioCommitments {
+  type Error = json::Error;
+
+  fn try_from(commitments: &RevelioCommitmentsJson) -> Result<RevelioCommitments, json::Error> {
+    Ok(RevelioCommitments {
+      v1: point_from_hex(&commitments.v1)?,
+      v2: point_from_hex(&commitments.v2)?,
+      v3: point_from_hex(&commitments.v3)?,
+    })
+  }
+}
+
+#[derive(Clone)]
 pub struct RepresentationPoK {
   c:  SecretKey,
   s1: SecretKey,
   s2: SecretKey,
 }
 
+impl Default for RepresentationPoK {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl RepresentationPoK {
   pub fn new() -> RepresentationPoK {
     RepresentationPoK {
@@ -250,97 +689,278 @@ impl RepresentationPoK {
     }
   }
 
-pub fn create_representation_spk (
+  #[allow(clippy::too_many_arguments)]
+  pub fn create_representation_spk (
+    secp_inst: &Secp256k1,
     output: PublicKey,
     blinding_factor: SecretKey, // alpha
     amount: u64,                // beta
     blinding_gen: PublicKey,    // G
     value_gen: PublicKey,       // H
-  ) -> RepresentationPoK {
-    let mut rng = thread_rng();
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-
+    nonce: Option<&[u8]>,       // verifier nonce
+    mode: NonceMode,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<RepresentationPoK, Error> {
+    let amount_key = RevelioPoK::amount_to_key(secp_inst, amount)?;
+
+    // Transcript of the statement S, completed with V below
+    let mut transcript = Transcript::new(secp_inst, REPRESENTATION_POK_NAME);
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"G", &blinding_gen);           // Hash G
+    transcript.append_point(b"H", &value_gen);              // Hash H
+    transcript.append_point(b"C", &output);                 // Hash \sum C_i
+
+    let nonces = transcript.nonce_generator(&[&blinding_factor, &amount_key], mode, rng);
     let mut rep_spk = RepresentationPoK::new();
-    let r1 = SecretKey::new(&secp_inst, &mut rng);
-    let r2 = SecretKey::new(&secp_inst, &mut rng);
+    let r1 = nonces.scalar(b"r_1");
+    let r2 = nonces.scalar(b"r_2");
 
     // Calculation of r_1*G + r_2*H
-    let r1_g = PublicKey::from_secret_key(&secp_inst, &r1).unwrap();
-    let mut r2_h = value_gen.clone();
-    r2_h.mul_assign(&secp_inst, &r2).unwrap();
-    let r1g_r2h = PublicKey::from_combination(&secp_inst, vec![&r1_g, &r2_h]).unwrap();
+    let r1_g = PublicKey::from_secret_key(secp_inst, &r1)?;
+    let mut r2_h = value_gen;
+    r2_h.mul_assign(secp_inst, &r2)?;
+    let r1g_r2h = PublicKey::from_combination(secp_inst, vec![&r1_g, &r2_h])?;
 
     // Calculation of H(S || r_1*G + r_2*H)
-    let mut hasher = Sha256::new();
-    hasher.input(blinding_gen.serialize_vec(&secp_inst, true)); // Hash G
-    hasher.input(value_gen.serialize_vec(&secp_inst, true));    // Hash H
-    hasher.input(output.serialize_vec(&secp_inst, true));       // Hash \sum C_i
-    hasher.input(r1g_r2h.serialize_vec(&secp_inst, true));      // Hash r_1*G + r_2*H
+    transcript.append_point(b"V", &r1g_r2h);                // Hash r_1*G + r_2*H
 
-    let hash_scalar = SecretKey::from_slice(&secp_inst, &hasher.result()).unwrap();
+    let hash_scalar = transcript.challenge_scalar(b"c");
 
     // Calculation of c
     rep_spk.c = hash_scalar;                                       // c = H(S...V_3)
 
     // Calculation of s_1
     rep_spk.s1 = blinding_factor;                                  // s_1 = alpha
-    rep_spk.s1.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();    // s_1 = -alpha
-    rep_spk.s1.mul_assign(&secp_inst, &rep_spk.c).unwrap();         // s_1 = -c*alpha
-    rep_spk.s1.add_assign(&secp_inst, &r1).unwrap();               // s_1 = r_1 - c*alpha
+    rep_spk.s1.mul_assign(secp_inst, &MINUS_ONE_KEY)?;     // s_1 = -alpha
+    rep_spk.s1.mul_assign(secp_inst, &rep_spk.c)?;          // s_1 = -c*alpha
+    rep_spk.s1.add_assign(secp_inst, &r1)?;                // s_1 = r_1 - c*alpha
 
     // Calculation of s_2
-    rep_spk.s2 = RevelioPoK::amount_to_key(&secp_inst, amount);    // s_2 = beta
-    rep_spk.s2.mul_assign(&secp_inst, &MINUS_ONE_KEY).unwrap();    // s_2 = -beta
-    rep_spk.s2.mul_assign(&secp_inst, &rep_spk.c).unwrap();         // s_2 = -c*beta
-    rep_spk.s2.add_assign(&secp_inst, &r2).unwrap();               // s_2 = r_2 - c*beta
+    rep_spk.s2 = amount_key;                                       // s_2 = beta
+    rep_spk.s2.mul_assign(secp_inst, &MINUS_ONE_KEY)?;     // s_2 = -beta
+    rep_spk.s2.mul_assign(secp_inst, &rep_spk.c)?;          // s_2 = -c*beta
+    rep_spk.s2.add_assign(secp_inst, &r2)?;                // s_2 = r_2 - c*beta
 
-    rep_spk
+    Ok(rep_spk)
   }
 
   pub fn verify_representation_spk (
+    secp_inst: &Secp256k1,
     output: &PublicKey,
     blinding_gen: &PublicKey, // G
     value_gen: &PublicKey,    // H
+    nonce: Option<&[u8]>,     // verifier nonce
     rep_spk: &RepresentationPoK,
-  ) -> bool {
-    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
-
+  ) -> Result<bool, Error> {
     // Calculation of V = s_1*G + s_2*H + c*X    where X = \sum C_i
-    let s1_g = PublicKey::from_secret_key(&secp_inst, &rep_spk.s1).unwrap();
-    let mut s2_h = value_gen.clone();
-    s2_h.mul_assign(&secp_inst, &rep_spk.s2).unwrap();
-    let mut c_x = output.clone();
-    c_x.mul_assign(&secp_inst, &rep_spk.c).unwrap();
-    let v = PublicKey::from_combination(&secp_inst, vec![&s1_g, &s2_h, &c_x]).unwrap();
+    let s1_g = PublicKey::from_secret_key(secp_inst, &rep_spk.s1)?;
+    let mut s2_h = *value_gen;
+    s2_h.mul_assign(secp_inst, &rep_spk.s2)?;
+    let mut c_x = *output;
+    c_x.mul_assign(secp_inst, &rep_spk.c)?;
+    let v = PublicKey::from_combination(secp_inst, vec![&s1_g, &s2_h, &c_x])?;
 
     // Calculation of H(S || V)
-    let mut hasher = Sha256::new();
-    hasher.input(blinding_gen.serialize_vec(&secp_inst, true)); // Hash G
-    hasher.input(value_gen.serialize_vec(&secp_inst, true));    // Hash H
-    hasher.input(output.serialize_vec(&secp_inst, true));       // Hash \sum C_i
-    hasher.input(v.serialize_vec(&secp_inst, true));           // Hash V
+    let mut transcript = Transcript::new(secp_inst, REPRESENTATION_POK_NAME);
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"G", blinding_gen);            // Hash G
+    transcript.append_point(b"H", value_gen);               // Hash H
+    transcript.append_point(b"C", output);                  // Hash \sum C_i
+    transcript.append_point(b"V", &v);                      // Hash V
 
-    let hash_scalar = SecretKey::from_slice(&secp_inst, &hasher.result()).unwrap();
+    let hash_scalar = transcript.challenge_scalar(b"c");
 
-    rep_spk.c == hash_scalar
+    Ok(rep_spk.c == hash_scalar)
   }
 }
 
+impl Writeable for RepresentationPoK {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_scalar(&self.c);
+    writer.write_scalar(&self.s1);
+    writer.write_scalar(&self.s2);
+  }
+}
+
+impl Readable for RepresentationPoK {
+  fn read(reader: &mut Reader) -> Result<RepresentationPoK, ser::Error> {
+    Ok(RepresentationPoK {
+      c:  reader.read_scalar()?,
+      s1: reader.read_scalar()?,
+      s2: reader.read_scalar()?,
+    })
+  }
+}
 
+impl From<&RepresentationPoK> for RepresentationPoKJson {
+  fn from(rep_spk: &RepresentationPoK) -> RepresentationPoKJson {
+    RepresentationPoKJson {
+      c:  scalar_to_hex(&rep_spk.c),
+      s1: scalar_to_hex(&rep_spk.s1),
+      s2: scalar_to_hex(&rep_spk.s2),
+    }
+  }
+}
+
+impl TryFrom<&RepresentationPoKJson> for RepresentationPoK {
+  type Error = json::Error;
+
+  fn try_from(rep_spk: &RepresentationPoKJson) -> Result<RepresentationPoK, json::Error> {
+    Ok(RepresentationPoK {
+      c:  scalar_from_hex(&rep_spk.c)?,
+      s1: scalar_from_hex(&rep_spk.s1)?,
+      s2: scalar_from_hex(&rep_spk.s2)?,
+    })
+  }
+}
+
+/// Proof of knowledge of the discrete logarithm of a point with respect to a
+/// single generator, i.e. knowledge of x such that X = x*B
+#[derive(Clone)]
+pub struct DiscreteLogPoK {
+  c: SecretKey,
+  s: SecretKey,
+}
+
+impl Default for DiscreteLogPoK {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl DiscreteLogPoK {
+  pub fn new() -> DiscreteLogPoK {
+    DiscreteLogPoK {
+      c: ZERO_KEY,
+      s: ZERO_KEY,
+    }
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  pub fn create_dlog_spk (
+    secp_inst: &Secp256k1,
+    point: PublicKey,   // X
+    secret: SecretKey,  // x
+    base_gen: PublicKey, // B
+    statement: &[u8],    // digest of the whole statement
+    nonce: Option<&[u8]>, // verifier nonce
+    mode: NonceMode,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> Result<DiscreteLogPoK, Error> {
+    // Transcript of the statement S, completed with r*B below
+    let mut transcript = Transcript::new(secp_inst, DLOG_POK_NAME);
+    transcript.append_message(b"statement", statement);     // Hash statement digest
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"B", &base_gen);               // Hash B
+    transcript.append_point(b"X", &point);                  // Hash X
+
+    let nonces = transcript.nonce_generator(&[&secret], mode, rng);
+    let mut dlog_spk = DiscreteLogPoK::new();
+    let r = nonces.scalar(b"r");
+
+    // Calculation of r*B
+    let mut r_b = base_gen;
+    r_b.mul_assign(secp_inst, &r)?;
+
+    // Calculation of H(S || r*B)
+    transcript.append_point(b"V", &r_b);                    // Hash r*B
+
+    let hash_scalar = transcript.challenge_scalar(b"c");
+
+    // Calculation of c
+    dlog_spk.c = hash_scalar;                                      // c = H(S || r*B)
+
+    // Calculation of s
+    dlog_spk.s = secret;                                           // s = x
+    dlog_spk.s.mul_assign(secp_inst, &MINUS_ONE_KEY)?;     // s = -x
+    dlog_spk.s.mul_assign(secp_inst, &dlog_spk.c)?;        // s = -c*x
+    dlog_spk.s.add_assign(secp_inst, &r)?;                 // s = r - c*x
+
+    Ok(dlog_spk)
+  }
+
+  pub fn verify_dlog_spk (
+    secp_inst: &Secp256k1,
+    point: &PublicKey,    // X
+    base_gen: &PublicKey, // B
+    statement: &[u8],     // digest of the whole statement
+    nonce: Option<&[u8]>, // verifier nonce
+    dlog_spk: &DiscreteLogPoK,
+  ) -> Result<bool, Error> {
+    // Calculation of V = s*B + c*X
+    let mut s_b = *base_gen;
+    s_b.mul_assign(secp_inst, &dlog_spk.s)?;
+    let mut c_x = *point;
+    c_x.mul_assign(secp_inst, &dlog_spk.c)?;
+    let v = PublicKey::from_combination(secp_inst, vec![&s_b, &c_x])?;
+
+    // Calculation of H(S || V)
+    let mut transcript = Transcript::new(secp_inst, DLOG_POK_NAME);
+    transcript.append_message(b"statement", statement);     // Hash statement digest
+    transcript.append_option(b"nonce", nonce);              // Hash verifier nonce
+    transcript.append_point(b"B", base_gen);                // Hash B
+    transcript.append_point(b"X", point);                   // Hash X
+    transcript.append_point(b"V", &v);                      // Hash V
+
+    let hash_scalar = transcript.challenge_scalar(b"c");
+
+    Ok(dlog_spk.c == hash_scalar)
+  }
+}
+
+
+
+impl Writeable for DiscreteLogPoK {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_scalar(&self.c);
+    writer.write_scalar(&self.s);
+  }
+}
+
+impl Readable for DiscreteLogPoK {
+  fn read(reader: &mut Reader) -> Result<DiscreteLogPoK, ser::Error> {
+    Ok(DiscreteLogPoK {
+      c: reader.read_scalar()?,
+      s: reader.read_scalar()?,
+    })
+  }
+}
+
+impl From<&DiscreteLogPoK> for DiscreteLogPoKJson {
+  fn from(dlog_spk: &DiscreteLogPoK) -> DiscreteLogPoKJson {
+    DiscreteLogPoKJson {
+      c: scalar_to_hex(&dlog_spk.c),
+      s: scalar_to_hex(&dlog_spk.s),
+    }
+  }
+}
+
+impl TryFrom<&DiscreteLogPoKJson> for DiscreteLogPoK {
+  type Error = json::Error;
+
+  fn try_from(dlog_spk: &DiscreteLogPoKJson) -> Result<DiscreteLogPoK, json::Error> {
+    Ok(DiscreteLogPoK {
+      c: scalar_from_hex(&dlog_spk.c)?,
+      s: scalar_from_hex(&dlog_spk.s)?,
+    })
+  }
+}
 
 #[cfg(test)]
 mod test {
-  use rand::thread_rng;
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
   use secp256k1zkp as secp;
   use secp::Secp256k1;
   use secp::key::{SecretKey, PublicKey, ZERO_KEY, ONE_KEY};
-  use super::{RevelioPoK, RepresentationPoK, MINUS_ONE_KEY};
+  use super::{RevelioPoK, RepresentationPoK, DiscreteLogPoK, MINUS_ONE_KEY};
   use super::super::exchange::RevelioGrinExchange;
+  use super::super::transcript::NonceMode;
 
 
   #[test]
   fn revelio_decoy_spk_gen_verify() {
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(1);
     let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
 
     let (_sk, output) = secp_inst.generate_keypair(&mut rng).unwrap();
@@ -349,49 +969,71 @@ mod test {
                               .to_pubkey(&secp_inst).unwrap();                 // 1*G + 0*H
     let value_basepoint = Secp256k1::commit(&secp_inst, 1, ZERO_KEY).unwrap()
                               .to_pubkey(&secp_inst).unwrap();                 // 0*G + 1*H
-    let keyimage_basepoint = RevelioGrinExchange::create_keyimage(0, ONE_KEY);        // 1*G' +0*H
+    let keyimage_basepoint = RevelioGrinExchange::create_keyimage(0, ONE_KEY).unwrap(); // 1*G' +0*H
 
     let dkey = SecretKey::new(&secp_inst, &mut rng);
-    let mut keyimage = keyimage_basepoint.clone();
+    let mut keyimage = keyimage_basepoint;
     keyimage.mul_assign(&secp_inst, &dkey).unwrap();
 
     let rspk = RevelioPoK::create_spk_from_decoykey(
+                              &secp_inst,
                               output,
                               keyimage,
                               dkey,
                               blinding_basepoint,
                               value_basepoint,
                               keyimage_basepoint,
-                            );
+                              b"statement",
+                              None,
+                              NonceMode::Random,
+                              &mut rng,
+                            ).unwrap();
     let result = RevelioPoK::verify_spk(
+                              &secp_inst,
                               &output,
                               &keyimage,
                               &blinding_basepoint,
                               &value_basepoint,
                               &keyimage_basepoint,
+                              b"statement",
+                              None,
                               &rspk,
-                            );
+                            ).unwrap();
     assert!(result);
+    // The proof is bound to the statement it was created for
+    let result = RevelioPoK::verify_spk(
+                              &secp_inst,
+                              &output,
+                              &keyimage,
+                              &blinding_basepoint,
+                              &value_basepoint,
+                              &keyimage_basepoint,
+                              b"other statement",
+                              None,
+                              &rspk,
+                            ).unwrap();
+    assert!(!result);
   }
 
   #[test]
   fn revelio_representation_spk_gen_verify() {
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(2);
     let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
 
     let blind = SecretKey::new(&secp_inst, &mut rng);
     let amount = 250u64;
-    let output = Secp256k1::commit(&secp_inst, amount, blind).unwrap()
+    let output = Secp256k1::commit(&secp_inst, amount, blind.clone()).unwrap()
                               .to_pubkey(&secp_inst).unwrap();
-    let keyimage = RevelioGrinExchange::create_keyimage(amount, blind);
+    let keyimage = RevelioGrinExchange::create_keyimage(amount, blind.clone()).unwrap();
 
     let blinding_basepoint = Secp256k1::commit(&secp_inst, 0, ONE_KEY).unwrap()
                               .to_pubkey(&secp_inst).unwrap();                 // 1*G + 0*H
     let value_basepoint = Secp256k1::commit(&secp_inst, 1, ZERO_KEY).unwrap()
                               .to_pubkey(&secp_inst).unwrap();                 // 0*G + 1*H
-    let keyimage_basepoint = RevelioGrinExchange::create_keyimage(0, ONE_KEY);        // 1*G' +0*H
+    let keyimage_basepoint = RevelioGrinExchange::create_keyimage(0, ONE_KEY).unwrap(); // 1*G' +0*H
 
     let rspk = RevelioPoK::create_spk_from_representation(
+                              &secp_inst,
                               output,
                               keyimage,
                               blind,
@@ -399,21 +1041,41 @@ mod test {
                               blinding_basepoint,
                               value_basepoint,
                               keyimage_basepoint,
-                            );
+                              b"statement",
+                              Some(b"nonce"),
+                              NonceMode::Random,
+                              &mut rng,
+                            ).unwrap();
     let result = RevelioPoK::verify_spk(
+                              &secp_inst,
                               &output,
                               &keyimage,
                               &blinding_basepoint,
                               &value_basepoint,
                               &keyimage_basepoint,
+                              b"statement",
+                              Some(b"nonce"),
                               &rspk,
-                            );
+                            ).unwrap();
     assert!(result);
+    // The proof is bound to the verifier nonce it was created for
+    let result = RevelioPoK::verify_spk(
+                              &secp_inst,
+                              &output,
+                              &keyimage,
+                              &blinding_basepoint,
+                              &value_basepoint,
+                              &keyimage_basepoint,
+                              b"statement",
+                              None,
+                              &rspk,
+                            ).unwrap();
+    assert!(!result);
   }
 
   #[test]
   fn simple_representation_spk_gen_verify() {
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(3);
     let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
 
     let blind1 = SecretKey::new(&secp_inst, &mut rng);
@@ -421,9 +1083,9 @@ mod test {
     let amount1 = 250u64;
     let amount2 = 350u64;
 
-    let output1 = Secp256k1::commit(&secp_inst, amount1, blind1).unwrap()
+    let output1 = Secp256k1::commit(&secp_inst, amount1, blind1.clone()).unwrap()
                               .to_pubkey(&secp_inst).unwrap();
-    let output2 = Secp256k1::commit(&secp_inst, amount2, blind2).unwrap()
+    let output2 = Secp256k1::commit(&secp_inst, amount2, blind2.clone()).unwrap()
                               .to_pubkey(&secp_inst).unwrap();
 
     let blinding_basepoint = Secp256k1::commit(&secp_inst, 0, ONE_KEY).unwrap()
@@ -431,32 +1093,76 @@ mod test {
     let value_basepoint = Secp256k1::commit(&secp_inst, 1, ZERO_KEY).unwrap()
                               .to_pubkey(&secp_inst).unwrap();                 // 0*G + 1*H
 
-    let mut blind = blind1;
+    let mut blind = blind1.clone();
     blind.add_assign(&secp_inst, &blind2).unwrap();
 
     let output = PublicKey::from_combination(&secp_inst, vec![&output1, &output2]).unwrap();
     let amount = amount1 + amount2;
 
     let rep_spk = RepresentationPoK::create_representation_spk(
+                    &secp_inst,
                     output,
                     blind,
                     amount,
                     blinding_basepoint,
                     value_basepoint,
-                  );
+                    Some(b"nonce"),
+                    NonceMode::Random,
+                    &mut rng,
+                  ).unwrap();
     let result = RepresentationPoK::verify_representation_spk(
+                    &secp_inst,
                     &output,
                     &blinding_basepoint,
                     &value_basepoint,
+                    Some(b"nonce"),
                     &rep_spk,
-                  );
+                  ).unwrap();
 
     assert!(result);
+
+    let result = RepresentationPoK::verify_representation_spk(
+                    &secp_inst,
+                    &output,
+                    &blinding_basepoint,
+                    &value_basepoint,
+                    Some(b"other nonce"),
+                    &rep_spk,
+                  ).unwrap();
+
+    assert!(!result);
+  }
+
+  #[test]
+  fn dlog_spk_gen_verify() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+
+    let keyimage_basepoint = RevelioGrinExchange::create_keyimage(0, ONE_KEY).unwrap(); // 1*G' +0*H
+    let secret = SecretKey::new(&secp_inst, &mut rng);
+    let mut point = keyimage_basepoint;
+    point.mul_assign(&secp_inst, &secret).unwrap();                                    // x*G'
+
+    let dlog_spk = DiscreteLogPoK::create_dlog_spk(
+                    &secp_inst,
+                    point,
+                    secret,
+                    keyimage_basepoint,
+                    b"statement",
+                    None,
+                    NonceMode::Random,
+                    &mut rng,
+                  ).unwrap();
+    assert!(DiscreteLogPoK::verify_dlog_spk(&secp_inst, &point, &keyimage_basepoint, b"statement", None, &dlog_spk).unwrap());
+    assert!(!DiscreteLogPoK::verify_dlog_spk(&secp_inst, &point, &keyimage_basepoint, b"other statement", None, &dlog_spk).unwrap());
+
+    let other_point = PublicKey::from_secret_key(&secp_inst, &ONE_KEY).unwrap();
+    assert!(!DiscreteLogPoK::verify_dlog_spk(&secp_inst, &other_point, &keyimage_basepoint, b"statement", None, &dlog_spk).unwrap());
   }
 
   #[test]
   fn check_minus_one_key() {
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(5);
     let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
 
     let (mut sk, pk) = secp_inst.generate_keypair(&mut rng).unwrap();
@@ -470,7 +1176,7 @@ mod test {
 
   #[test]
   fn check_amount_to_key() {
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(6);
     let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
 
     let (sk, pk) = secp_inst.generate_keypair(&mut rng).unwrap();  // pk = sk*G
@@ -480,9 +1186,9 @@ mod test {
 
     let value_basepoint = Secp256k1::commit(&secp_inst, 1, ZERO_KEY).unwrap()
                               .to_pubkey(&secp_inst).unwrap();     // 0*G + 1*H
-    let amount_scalar = RevelioPoK::amount_to_key(&secp_inst, amount);
+    let amount_scalar = RevelioPoK::amount_to_key(&secp_inst, amount).unwrap();
 
-    let mut ah = value_basepoint.clone();
+    let mut ah = value_basepoint;
     ah.mul_assign(&secp_inst, &amount_scalar).unwrap();    //25*H
     let skg_ah = PublicKey::from_combination(&secp_inst, vec![&pk, &ah]).unwrap(); // sk*G + 25*H
 
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "parallel")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::error::Error;
+
+/// Number of threads used for work over an anonymity list, 0 meaning one per CPU
+static NUM_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Pool built by set_num_threads for a thread count above one. Other counts
+/// use the calling thread or rayon's global pool.
+#[cfg(feature = "parallel")]
+static POOL: Mutex<Option<Arc<rayon::ThreadPool>>> = Mutex::new(None);
+
+/// Sets the number of threads which proof generation, key image computation
+/// and verification spread the outputs over. Zero uses one thread per CPU
+/// and one keeps all work on the calling thread. Without the parallel
+/// feature all work stays on the calling thread whatever the setting.
+///
+/// Fails if the thread pool cannot be built, in which case the previous
+/// setting is kept.
+pub fn set_num_threads(num_threads: usize) -> Result<(), Error> {
+  #[cfg(feature = "parallel")]
+  {
+    let pool = match num_threads {
+      0 | 1 => None,
+      k => match rayon::ThreadPoolBuilder::new().num_threads(k).build() {
+        Ok(pool) => Some(Arc::new(pool)),
+        Err(e) => return Err(Error::ThreadPool(e.to_string())),
+      },
+    };
+    let mut current = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    *current = pool;
+    NUM_THREADS.store(num_threads, Ordering::Relaxed);
+  }
+  #[cfg(not(feature = "parallel"))]
+  NUM_THREADS.store(num_threads, Ordering::Relaxed);
+  Ok(())
+}
+
+/// Returns the thread count set with set_num_threads
+pub fn num_threads() -> usize {
+  NUM_THREADS.load(Ordering::Relaxed)
+}
+
+/// Calls f on every index in 0..n and returns the results in index order
+#[cfg(feature = "parallel")]
+pub(crate) fn map_indices<T, F>(n: usize, f: F) -> Vec<T>
+where
+  T: Send,
+  F: Fn(usize) -> T + Sync + Send,
+{
+  if num_threads() == 1 {
+    return (0..n).map(f).collect();
+  }
+  let pool = POOL.lock().unwrap_or_else(|e| e.into_inner()).clone();
+  match pool {
+    Some(pool) => pool.install(|| (0..n).into_par_iter().map(&f).collect()),
+    None => (0..n).into_par_iter().map(f).collect(),
+  }
+}
+
+/// Calls f on every index in 0..n and returns the results in index order
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn map_indices<T, F>(n: usize, f: F) -> Vec<T>
+where
+  F: Fn(usize) -> T,
+{
+  (0..n).map(f).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::map_indices;
+
+  #[test]
+  fn results_keep_index_order() {
+    let squares = map_indices(1000, |i| i * i);
+    assert!(squares.iter().enumerate().all(|(i, s)| *s == i * i));
+    assert!(map_indices(0, |i| i).is_empty());
+  }
+}
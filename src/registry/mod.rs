@@ -0,0 +1,443 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+use rusqlite::{params, Connection, OptionalExtension};
+use secp256k1zkp as secp;
+use secp::key::PublicKey;
+
+use super::audit::{KeyImageOccurrence, SharedKeyImage};
+use super::context::secp_context;
+use super::exchange::RevelioProof;
+use super::ser::{self, Reader, REVELIO_PROOF_TAG};
+
+const SCHEMA: &str = "
+  CREATE TABLE IF NOT EXISTS proofs (
+    id INTEGER PRIMARY KEY,
+    submitter TEXT NOT NULL,
+    exchange_id TEXT NOT NULL,
+    epoch INTEGER NOT NULL,
+    total_amount INTEGER NOT NULL,
+    anon_list_size INTEGER NOT NULL,
+    statement_digest BLOB NOT NULL,
+    UNIQUE (exchange_id, epoch)
+  );
+  CREATE TABLE IF NOT EXISTS keyimages (
+    proof_id INTEGER NOT NULL REFERENCES proofs (id),
+    position INTEGER NOT NULL,
+    keyimage BLOB NOT NULL,
+    output BLOB NOT NULL,
+    PRIMARY KEY (proof_id, position)
+  );
+  CREATE INDEX IF NOT EXISTS keyimages_by_keyimage ON keyimages (keyimage);
+  CREATE INDEX IF NOT EXISTS keyimages_by_output ON keyimages (output);
+";
+
+const ENTRY_QUERY: &str = "
+  SELECT p.submitter, p.exchange_id, p.epoch, k.position, k.keyimage, k.output
+  FROM keyimages k JOIN proofs p ON p.id = k.proof_id";
+
+/// Errors returned by a KeyImageRegistry
+#[derive(Debug)]
+pub enum Error {
+  /// The database could not be opened, read or written
+  Db(rusqlite::Error),
+  /// A proof could not be decoded
+  Ser(ser::Error),
+  /// A proof could not be decoded from JSON
+  Json(super::json::Error),
+  /// A proof does not verify
+  InvalidProof(super::Error),
+  /// A proof of this exchange and epoch is already registered
+  AlreadyRegistered { exchange_id: String, epoch: u64 },
+  /// Proofs of this exchange id were registered by another submitter
+  ExchangeIdTaken { exchange_id: String, submitter: String },
+  /// A number does not fit in an SQLite integer, or a stored number or
+  /// statement digest is invalid. Malformed stored points are reported as Ser.
+  Corrupt(&'static str),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::Db(ref e) => write!(f, "database error: {}", e),
+      Error::Ser(ref e) => write!(f, "{}", e),
+      Error::Json(ref e) => write!(f, "{}", e),
+      Error::InvalidProof(ref e) => write!(f, "invalid proof: {}", e),
+      Error::AlreadyRegistered { ref exchange_id, epoch } =>
+        write!(f, "a proof of exchange {:?} for epoch {} is already registered", exchange_id, epoch),
+      Error::ExchangeIdTaken { ref exchange_id, ref submitter } =>
+        write!(f, "exchange id {:?} is registered to {:?}", exchange_id, submitter),
+      Error::Corrupt(what) => write!(f, "invalid {}", what),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+  fn from(e: rusqlite::Error) -> Error {
+    Error::Db(e)
+  }
+}
+
+impl From<ser::Error> for Error {
+  fn from(e: ser::Error) -> Error {
+    Error::Ser(e)
+  }
+}
+
+impl From<super::json::Error> for Error {
+  fn from(e: super::json::Error) -> Error {
+    Error::Json(e)
+  }
+}
+
+/// A key image of a registered proof together with its output
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegistryEntry {
+  pub submitter: String,
+  pub exchange_id: String,
+  pub epoch: u64,
+  /// Index of the output in the anonymity list of the proof
+  pub index: usize,
+  pub keyimage: PublicKey,
+  pub output: PublicKey,
+}
+
+/// A registered proof
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegisteredProof {
+  pub submitter: String,
+  pub exchange_id: String,
+  pub epoch: u64,
+  pub total_amount: u64,
+  pub anon_list_size: usize,
+  pub statement_digest: [u8; 32],
+}
+
+/// Record of the key images published by exchanges, kept in an SQLite
+/// database.
+///
+/// Every verified proof adds one row per output holding the exchange id, the
+/// epoch, which is the block height in the context of the proof, the key
+/// image and the output. Key images are indexed, so finding the proofs
+/// which contain a key image does not scan the table.
+///
+/// Each proof is registered under the name of the party which submitted it.
+/// Exchange ids are chosen by the exchanges themselves, so an exchange id
+/// once registered by a submitter cannot be used by another one, and shared
+/// key images are found among the proofs of different submitters.
+pub struct KeyImageRegistry {
+  conn: Connection,
+}
+
+impl KeyImageRegistry {
+  /// Opens the registry at path, creating it if it does not exist
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<KeyImageRegistry, Error> {
+    KeyImageRegistry::init(Connection::open(path)?)
+  }
+
+  /// Opens a registry which lives in memory, for tests and one-off checks
+  pub fn open_in_memory() -> Result<KeyImageRegistry, Error> {
+    KeyImageRegistry::init(Connection::open_in_memory()?)
+  }
+
+  fn init(conn: Connection) -> Result<KeyImageRegistry, Error> {
+    // SQLite leaves REFERENCES unchecked unless enabled on every connection
+    conn.pragma_update(None, "foreign_keys", true)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(KeyImageRegistry { conn })
+  }
+
+  /// Verifies a proof submitted by `submitter` and records its key images. A
+  /// second proof of the same exchange and epoch is rejected, and so is a
+  /// proof whose exchange id was registered by another submitter.
+  pub fn ingest(&mut self, submitter: &str, proof: &RevelioProof) -> Result<(), Error> {
+    proof.verify().map_err(Error::InvalidProof)?;
+
+    let exchange_id = &proof.context.exchange_id;
+    let epoch = proof.context.block_height;
+    let tx = self.conn.transaction()?;
+    let owner: Option<String> = tx.query_row(
+                                  "SELECT submitter FROM proofs WHERE exchange_id = ?1 LIMIT 1",
+                                  params![exchange_id],
+                                  |row| row.get(0),
+                                ).optional()?;
+    if let Some(owner) = owner {
+      if owner != submitter {
+        return Err(Error::ExchangeIdTaken { exchange_id: exchange_id.clone(), submitter: owner });
+      }
+    }
+    let registered: Option<i64> = tx.query_row(
+                                    "SELECT id FROM proofs WHERE exchange_id = ?1 AND epoch = ?2",
+                                    params![exchange_id, to_sql_int(epoch, "epoch")?],
+                                    |row| row.get(0),
+                                  ).optional()?;
+    if registered.is_some() {
+      return Err(Error::AlreadyRegistered { exchange_id: exchange_id.clone(), epoch });
+    }
+
+    tx.execute(
+      "INSERT INTO proofs (submitter, exchange_id, epoch, total_amount, anon_list_size, statement_digest)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+      params![
+        submitter,
+        exchange_id,
+        to_sql_int(epoch, "epoch")?,
+        to_sql_int(proof.total_amount, "total amount")?,
+        to_sql_int(proof.anon_list.len() as u64, "anonymity list size")?,
+        &proof.statement_digest()[..],
+      ],
+    )?;
+    let proof_id = tx.last_insert_rowid();
+    {
+      let mut insert = tx.prepare(
+                         "INSERT INTO keyimages (proof_id, position, keyimage, output) VALUES (?1, ?2, ?3, ?4)")?;
+      let secp_inst = secp_context();
+      for (i, (output, keyimage)) in proof.anon_list.iter().zip(&proof.keyimage_list).enumerate() {
+        insert.execute(params![
+          proof_id,
+          i as i64,
+          keyimage.serialize_vec(secp_inst, true).to_vec(),
+          output.serialize_vec(secp_inst, true).to_vec(),
+        ])?;
+      }
+    }
+    tx.commit()?;
+    Ok(())
+  }
+
+  /// Decodes a proof in the binary or the JSON encoding, verifies it and
+  /// records its key images like ingest
+  pub fn ingest_bytes(&mut self, submitter: &str, bytes: &[u8]) -> Result<(), Error> {
+    let is_json = bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{');
+    let proof = if is_json {
+      let text = std::str::from_utf8(bytes).map_err(|_| ser::Error::InvalidString)?;
+      RevelioProof::from_json(text)?
+    } else if bytes.get(1) == Some(&REVELIO_PROOF_TAG) {
+      ser::deserialize(bytes)?
+    } else {
+      return Err(Error::Ser(ser::Error::UnexpectedTag { expected: REVELIO_PROOF_TAG, found: bytes.get(1).copied().unwrap_or(0) }));
+    };
+    self.ingest(submitter, &proof)
+  }
+
+  /// The registered proofs, ordered by epoch and exchange id
+  pub fn proofs(&self) -> Result<Vec<RegisteredProof>, Error> {
+    let mut query = self.conn.prepare(
+                      "SELECT submitter, exchange_id, epoch, total_amount, anon_list_size, statement_digest
+                       FROM proofs ORDER BY epoch, exchange_id")?;
+    let rows = query.query_map([], |row| {
+      Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?,
+          row.get::<_, i64>(3)?, row.get::<_, i64>(4)?, row.get::<_, Vec<u8>>(5)?))
+    })?;
+
+    let mut proofs = Vec::new();
+    for row in rows {
+      let (submitter, exchange_id, epoch, total_amount, anon_list_size, digest) = row?;
+      let mut statement_digest = [0u8; 32];
+      if digest.len() != statement_digest.len() {
+        return Err(Error::Corrupt("statement digest"));
+      }
+      statement_digest.copy_from_slice(&digest);
+      proofs.push(RegisteredProof {
+        submitter,
+        exchange_id,
+        epoch: from_sql_int(epoch, "epoch")?,
+        total_amount: from_sql_int(total_amount, "total amount")?,
+        anon_list_size: from_sql_int(anon_list_size, "anonymity list size")? as usize,
+        statement_digest,
+      });
+    }
+    Ok(proofs)
+  }
+
+  /// Every registered occurrence of a key image
+  pub fn find_keyimage(&self, keyimage: &PublicKey) -> Result<Vec<RegistryEntry>, Error> {
+    self.entries(
+      &format!("{} WHERE k.keyimage = ?1 ORDER BY p.epoch, p.exchange_id", ENTRY_QUERY),
+      params![keyimage.serialize_vec(secp_context(), true).to_vec()],
+    )
+  }
+
+  /// Every registered occurrence of an output, with the key image each
+  /// exchange published for it
+  pub fn find_output(&self, output: &PublicKey) -> Result<Vec<RegistryEntry>, Error> {
+    self.entries(
+      &format!("{} WHERE k.output = ?1 ORDER BY p.epoch, p.exchange_id", ENTRY_QUERY),
+      params![output.serialize_vec(secp_context(), true).to_vec()],
+    )
+  }
+
+  /// Key images published by more than one submitter, in the given epoch or
+  /// in any epoch, by the rule of [`SharedKeyImage::from_occurrences`]. See
+  /// [`find_shared_keyimages`](super::audit::find_shared_keyimages) for what
+  /// such a key image means.
+  pub fn shared_keyimages(&self, epoch: Option<u64>) -> Result<Vec<SharedKeyImage>, Error> {
+    let epoch = epoch.map(|epoch| to_sql_int(epoch, "epoch")).transpose()?;
+    let mut query = self.conn.prepare(
+                      "SELECT k.keyimage FROM keyimages k JOIN proofs p ON p.id = k.proof_id
+                       WHERE ?1 IS NULL OR p.epoch = ?1
+                       GROUP BY k.keyimage HAVING COUNT(DISTINCT p.submitter) > 1
+                       ORDER BY MIN(p.epoch), k.keyimage")?;
+    let keyimages = query.query_map(params![epoch], |row| row.get::<_, Vec<u8>>(0))?
+                      .collect::<Result<Vec<_>, _>>()?;
+
+    let mut shared = Vec::with_capacity(keyimages.len());
+    for bytes in keyimages {
+      let entries = self.entries(
+                      &format!("{} WHERE k.keyimage = ?1 AND (?2 IS NULL OR p.epoch = ?2)
+                                ORDER BY p.epoch, p.exchange_id", ENTRY_QUERY),
+                      params![bytes, epoch],
+                    )?;
+      let occurrences = entries.into_iter().map(|entry| KeyImageOccurrence {
+                          label: entry.submitter,
+                          exchange_id: entry.exchange_id,
+                          epoch: entry.epoch,
+                          index: entry.index,
+                          output: entry.output,
+                        }).collect();
+      shared.extend(SharedKeyImage::from_occurrences(point_from_blob(&bytes)?, occurrences));
+    }
+    Ok(shared)
+  }
+
+  fn entries(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<RegistryEntry>, Error> {
+    let mut query = self.conn.prepare(sql)?;
+    let rows = query.query_map(params, |row| {
+      Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?,
+          row.get::<_, i64>(3)?, row.get::<_, Vec<u8>>(4)?, row.get::<_, Vec<u8>>(5)?))
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+      let (submitter, exchange_id, epoch, index, keyimage, output) = row?;
+      entries.push(RegistryEntry {
+        submitter,
+        exchange_id,
+        epoch: from_sql_int(epoch, "epoch")?,
+        index: from_sql_int(index, "index")? as usize,
+        keyimage: point_from_blob(&keyimage)?,
+        output: point_from_blob(&output)?,
+      });
+    }
+    Ok(entries)
+  }
+}
+
+/// SQLite integers are signed 64-bit
+fn to_sql_int(n: u64, what: &'static str) -> Result<i64, Error> {
+  i64::try_from(n).map_err(|_| Error::Corrupt(what))
+}
+
+fn from_sql_int(n: i64, what: &'static str) -> Result<u64, Error> {
+  u64::try_from(n).map_err(|_| Error::Corrupt(what))
+}
+
+fn point_from_blob(bytes: &[u8]) -> Result<PublicKey, Error> {
+  let mut reader = Reader::new(bytes);
+  let point = reader.read_point()?;
+  reader.finish()?;
+  Ok(point)
+}
+
+#[cfg(test)]
+mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+  use secp256k1zkp as secp;
+  use secp::Secp256k1;
+  use secp::key::{SecretKey, PublicKey};
+  use super::{KeyImageRegistry, Error};
+  use crate::context::secp_context;
+  use crate::exchange::{RevelioGrinExchange, RevelioProof, ProofContext, DecoySeed, OwnOutput};
+  use crate::ser;
+
+  fn own_output(amount: u64, rng: &mut StdRng) -> OwnOutput {
+    let secp_inst = secp_context();
+    let blinding = SecretKey::new(secp_inst, rng);
+    let commitment = Secp256k1::commit(secp_inst, amount, blinding.clone()).unwrap()
+                       .to_pubkey(secp_inst).unwrap();
+    (commitment, blinding, amount)
+  }
+
+  fn prove(
+    exchange_id: &str,
+    epoch: u64,
+    anon_list: &[PublicKey],
+    own_outputs: Vec<OwnOutput>,
+    seed: &DecoySeed,
+    rng: &mut StdRng,
+  ) -> RevelioProof {
+    let mut grin_exch = RevelioGrinExchange::from_outputs(anon_list.to_vec(), own_outputs, seed.clone(), rng).unwrap();
+    grin_exch.set_context(ProofContext { exchange_id: exchange_id.to_string(), block_height: epoch });
+    grin_exch.generate_proof(rng).unwrap()
+  }
+
+  #[test]
+  fn record_and_query_keyimages() {
+    let mut rng = StdRng::seed_from_u64(28);
+    let secp_inst = secp_context();
+    let mut snapshot = vec![];
+    for _i in 0..10 {
+      let sk = SecretKey::new(secp_inst, &mut rng);
+      snapshot.push(PublicKey::from_secret_key(secp_inst, &sk).unwrap());
+    }
+    let own_a = own_output(3, &mut rng);
+    let shared = own_output(5, &mut rng);
+    snapshot.push(own_a.0);
+    snapshot.push(shared.0);
+
+    // exch-b also counts the shared output, whose opening it got from exch-a
+    let (seed_a, seed_b) = (DecoySeed::random(&mut rng), DecoySeed::random(&mut rng));
+    let a1 = prove("exch-a", 100, &snapshot, vec![own_a.clone(), shared.clone()], &seed_a, &mut rng);
+    let a2 = prove("exch-a", 200, &snapshot, vec![own_a.clone(), shared.clone()], &seed_a, &mut rng);
+    let b2 = prove("exch-b", 200, &snapshot, vec![shared.clone()], &seed_b, &mut rng);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("registry.db");
+    {
+      let mut registry = KeyImageRegistry::open(&path).unwrap();
+      registry.ingest("a", &a1).unwrap();
+      registry.ingest_bytes("a", &ser::serialize(&a2)).unwrap();
+      registry.ingest_bytes("b", b2.to_json().as_bytes()).unwrap();
+      assert!(matches!(registry.ingest("a", &a1), Err(Error::AlreadyRegistered { epoch: 100, .. })));
+
+      let mut invalid = b2.clone();
+      invalid.context.block_height = 300;
+      assert!(matches!(registry.ingest("b", &invalid), Err(Error::InvalidProof(_))));
+
+      // exch-b cannot hide its proof under the exchange id of exch-a
+      let b3 = prove("exch-a", 300, &snapshot, vec![shared.clone()], &seed_b, &mut rng);
+      assert!(matches!(registry.ingest("b", &b3), Err(Error::ExchangeIdTaken { ref submitter, .. }) if submitter == "a"));
+    }
+
+    // The registry persists on disk, and key images cannot refer to a proof
+    // which is not registered
+    let registry = KeyImageRegistry::open(&path).unwrap();
+    assert!(registry.conn.execute(
+              "INSERT INTO keyimages (proof_id, position, keyimage, output) VALUES (99, 0, x'00', x'00')", []).is_err());
+    let proofs = registry.proofs().unwrap();
+    assert!(proofs.iter().map(|p| (p.exchange_id.as_str(), p.epoch)).collect::<Vec<_>>() ==
+            vec![("exch-a", 100), ("exch-a", 200), ("exch-b", 200)]);
+    assert!(proofs[2].submitter == "b" && proofs[2].total_amount == 5 && proofs[2].statement_digest == b2.statement_digest());
+
+    // The key image of an own output is found in both epochs
+    let i = a1.anon_list.iter().position(|c| *c == own_a.0).unwrap();
+    let entries = registry.find_keyimage(&a1.keyimage_list[i]).unwrap();
+    assert!(entries.iter().map(|e| e.epoch).collect::<Vec<_>>() == vec![100, 200]);
+    assert!(entries.iter().all(|e| e.output == own_a.0 && e.index == i));
+
+    // A decoy has one key image per exchange
+    let entries = registry.find_output(&snapshot[0]).unwrap();
+    assert!(entries.len() == 3);
+    assert!(entries[0].keyimage == entries[1].keyimage && entries[1].keyimage != entries[2].keyimage);
+
+    let collusions = registry.shared_keyimages(None).unwrap();
+    assert!(collusions.len() == 1);
+    assert!(collusions[0].occurrences.len() == 3);
+    assert!(collusions[0].occurrences.iter().all(|e| e.output == shared.0));
+    assert!(registry.shared_keyimages(Some(200)).unwrap()[0].occurrences.len() == 2);
+    assert!(registry.shared_keyimages(Some(100)).unwrap().is_empty());
+  }
+}
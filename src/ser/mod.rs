@@ -0,0 +1,387 @@
+use std::fmt;
+use secp256k1zkp as secp;
+use secp::Secp256k1;
+use secp::key::{SecretKey, PublicKey};
+
+use super::context::secp_context;
+
+/// Version of the binary proof format
+pub const PROTOCOL_VERSION: u8 = 7;
+
+/// Tag identifying a serialized SimpleProof
+pub const SIMPLE_PROOF_TAG: u8 = 0x01;
+
+/// Tag identifying a serialized RevelioProof
+pub const REVELIO_PROOF_TAG: u8 = 0x02;
+
+/// Tag identifying a RevelioProof written as a stream of output records
+pub const REVELIO_STREAM_TAG: u8 = 0x03;
+
+/// Tag identifying a checkpoint of Revelio proof generation
+pub const REVELIO_CHECKPOINT_TAG: u8 = 0x04;
+
+/// Size of a compressed curve point
+pub const POINT_SIZE: usize = 33;
+
+/// Size of a scalar
+pub const SCALAR_SIZE: usize = 32;
+
+/// The order of the secp256k1 group
+pub const CURVE_ORDER: [u8; SCALAR_SIZE] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b,
+    0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41
+];
+
+/// Errors encountered while decoding a proof
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+  /// The input ended before the structure was fully read
+  UnexpectedEof,
+  /// The input has bytes left over after the structure was read
+  TrailingBytes(usize),
+  /// The format version is not supported
+  UnsupportedVersion(u8),
+  /// The tag does not match the expected proof type
+  UnexpectedTag { expected: u8, found: u8 },
+  /// A point is not a valid compressed curve point
+  InvalidPoint,
+  /// A scalar is not less than the group order
+  NonCanonicalScalar,
+  /// A list length exceeds the remaining input
+  InvalidLength(u64),
+  /// A string is not valid UTF-8
+  InvalidString,
+  /// A presence flag is neither 0 nor 1
+  InvalidFlag(u8),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::UnexpectedEof => write!(f, "unexpected end of input"),
+      Error::TrailingBytes(n) => write!(f, "{} trailing bytes after proof", n),
+      Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+      Error::UnexpectedTag { expected, found } =>
+        write!(f, "unexpected proof tag {:#04x}, expected {:#04x}", found, expected),
+      Error::InvalidPoint => write!(f, "invalid curve point"),
+      Error::NonCanonicalScalar => write!(f, "non-canonical scalar"),
+      Error::InvalidLength(n) => write!(f, "invalid list length {}", n),
+      Error::InvalidString => write!(f, "invalid UTF-8 string"),
+      Error::InvalidFlag(flag) => write!(f, "invalid presence flag {}", flag),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+/// Types which can be written in the binary proof format
+pub trait Writeable {
+  fn write(&self, writer: &mut Writer);
+}
+
+/// Types which can be read from the binary proof format
+pub trait Readable: Sized {
+  fn read(reader: &mut Reader) -> Result<Self, Error>;
+}
+
+/// Serializes an object to bytes
+pub fn serialize<T: Writeable>(obj: &T) -> Vec<u8> {
+  let mut writer = Writer::new();
+  obj.write(&mut writer);
+  writer.into_bytes()
+}
+
+/// Deserializes an object from bytes, rejecting any trailing bytes
+pub fn deserialize<T: Readable>(bytes: &[u8]) -> Result<T, Error> {
+  let mut reader = Reader::new(bytes);
+  let obj = T::read(&mut reader)?;
+  reader.finish()?;
+  Ok(obj)
+}
+
+pub struct Writer {
+  secp_inst: &'static Secp256k1,
+  buf: Vec<u8>,
+}
+
+impl Default for Writer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Writer {
+  pub fn new() -> Writer {
+    Writer {
+      secp_inst: secp_context(),
+      buf: Vec::new(),
+    }
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.buf
+  }
+
+  pub fn write_u8(&mut self, n: u8) {
+    self.buf.push(n);
+  }
+
+  pub fn write_u64(&mut self, n: u64) {
+    self.buf.extend_from_slice(&n.to_be_bytes());
+  }
+
+  /// Writes the format version followed by the proof tag
+  pub fn write_header(&mut self, tag: u8) {
+    self.write_u8(PROTOCOL_VERSION);
+    self.write_u8(tag);
+  }
+
+  /// Writes the length of a byte string followed by its bytes
+  pub fn write_var_bytes(&mut self, bytes: &[u8]) {
+    self.write_u64(bytes.len() as u64);
+    self.buf.extend_from_slice(bytes);
+  }
+
+  pub fn write_point(&mut self, point: &PublicKey) {
+    self.buf.extend_from_slice(&point.serialize_vec(self.secp_inst, true));
+  }
+
+  pub fn write_scalar(&mut self, scalar: &SecretKey) {
+    self.buf.extend_from_slice(&scalar.0);
+  }
+
+  /// Writes bytes without their length, for data of a fixed size
+  pub fn write_fixed_bytes(&mut self, bytes: &[u8]) {
+    self.buf.extend_from_slice(bytes);
+  }
+
+  /// Writes the length of a list followed by its elements
+  pub fn write_list<T: Writeable>(&mut self, list: &[T]) {
+    self.write_u64(list.len() as u64);
+    for elem in list {
+      elem.write(self);
+    }
+  }
+
+  pub fn write_point_list(&mut self, list: &[PublicKey]) {
+    self.write_u64(list.len() as u64);
+    for point in list {
+      self.write_point(point);
+    }
+  }
+}
+
+pub struct Reader<'a> {
+  secp_inst: &'static Secp256k1,
+  buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(buf: &'a [u8]) -> Reader<'a> {
+    Reader {
+      secp_inst: secp_context(),
+      buf,
+    }
+  }
+
+  /// Number of bytes which have not been read yet
+  pub fn remaining(&self) -> usize {
+    self.buf.len()
+  }
+
+  /// Checks that the whole input has been read
+  pub fn finish(&self) -> Result<(), Error> {
+    match self.remaining() {
+      0 => Ok(()),
+      n => Err(Error::TrailingBytes(n)),
+    }
+  }
+
+  fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+    if self.buf.len() < len {
+      return Err(Error::UnexpectedEof);
+    }
+    let (bytes, rest) = self.buf.split_at(len);
+    self.buf = rest;
+    Ok(bytes)
+  }
+
+  pub fn read_u8(&mut self) -> Result<u8, Error> {
+    Ok(self.read_bytes(1)?[0])
+  }
+
+  pub fn read_u64(&mut self) -> Result<u64, Error> {
+    let mut n = [0u8; 8];
+    n.copy_from_slice(self.read_bytes(8)?);
+    Ok(u64::from_be_bytes(n))
+  }
+
+  /// Reads a byte string written by write_var_bytes
+  pub fn read_var_bytes(&mut self) -> Result<Vec<u8>, Error> {
+    let len = self.read_list_len(1)?;
+    Ok(self.read_bytes(len)?.to_vec())
+  }
+
+  /// Reads the format version and checks the proof tag
+  pub fn read_header(&mut self, tag: u8) -> Result<(), Error> {
+    let version = self.read_u8()?;
+    if version != PROTOCOL_VERSION {
+      return Err(Error::UnsupportedVersion(version));
+    }
+    let found = self.read_u8()?;
+    if found != tag {
+      return Err(Error::UnexpectedTag { expected: tag, found });
+    }
+    Ok(())
+  }
+
+  /// Reads a compressed point, rejecting bytes which are not on the curve
+  pub fn read_point(&mut self) -> Result<PublicKey, Error> {
+    let bytes = self.read_bytes(POINT_SIZE)?;
+    if bytes[0] != 0x02 && bytes[0] != 0x03 {
+      return Err(Error::InvalidPoint);
+    }
+    PublicKey::from_slice(self.secp_inst, bytes).map_err(|_| Error::InvalidPoint)
+  }
+
+  /// Reads a scalar, rejecting values which are not less than the group order
+  pub fn read_scalar(&mut self) -> Result<SecretKey, Error> {
+    let bytes = self.read_bytes(SCALAR_SIZE)?;
+    if bytes >= &CURVE_ORDER[..] {
+      return Err(Error::NonCanonicalScalar);
+    }
+    let mut scalar = [0u8; SCALAR_SIZE];
+    scalar.copy_from_slice(bytes);
+    Ok(SecretKey(scalar))
+  }
+
+  /// Reads N bytes written by write_fixed_bytes
+  pub fn read_fixed_bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+    let mut bytes = [0u8; N];
+    bytes.copy_from_slice(self.read_bytes(N)?);
+    Ok(bytes)
+  }
+
+  /// Reads a list length, checking that the list fits in the remaining input
+  /// given the encoded size of each element
+  pub fn read_list_len(&mut self, elem_size: usize) -> Result<usize, Error> {
+    let len = self.read_u64()?;
+    if len > (self.remaining() / elem_size) as u64 {
+      return Err(Error::InvalidLength(len));
+    }
+    Ok(len as usize)
+  }
+
+  pub fn read_list<T: Readable>(&mut self, elem_size: usize) -> Result<Vec<T>, Error> {
+    let len = self.read_list_len(elem_size)?;
+    let mut list = Vec::with_capacity(len);
+    for _i in 0..len {
+      list.push(T::read(self)?);
+    }
+    Ok(list)
+  }
+
+  pub fn read_point_list(&mut self) -> Result<Vec<PublicKey>, Error> {
+    let len = self.read_list_len(POINT_SIZE)?;
+    let mut list = Vec::with_capacity(len);
+    for _i in 0..len {
+      list.push(self.read_point()?);
+    }
+    Ok(list)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+  use super::{serialize, deserialize, Error, CURVE_ORDER, PROTOCOL_VERSION};
+  use super::super::nizk::PoKEncoding;
+  use super::super::exchange::{RevelioGrinExchange, SimpleGrinExchange, RevelioProof, SimpleProof, ProofContext};
+
+  #[test]
+  fn revelio_proof_roundtrip() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut grin_exch = RevelioGrinExchange::new(6, 2, &mut rng).unwrap();
+    let revelio_proof = grin_exch.generate_proof(&mut rng).unwrap();
+
+    let bytes = serialize(&revelio_proof);
+    assert!(bytes.len() == 2 + 3*33 + 2*8 + 1 + 8 + 2*32 + 3*8 + 1 + 6*(33 + 33 + 5*32));
+    let decoded: RevelioProof = deserialize(&bytes).unwrap();
+    assert!(decoded.verify().is_ok());
+    assert!(serialize(&decoded) == bytes);
+
+    // The commitment form adds V_1, V_2, V_3 to every output proof
+    grin_exch.set_encoding(PoKEncoding::Commitment);
+    let bytes = serialize(&grin_exch.generate_proof(&mut rng).unwrap());
+    assert!(bytes.len() == 2 + 3*33 + 2*8 + 1 + 8 + 2*32 + 3*8 + 1 + 6*(33 + 33 + 5*32 + 3*33));
+    let decoded: RevelioProof = deserialize(&bytes).unwrap();
+    assert!(decoded.encoding() == PoKEncoding::Commitment);
+    assert!(decoded.verify().is_ok());
+    assert!(serialize(&decoded) == bytes);
+    let mut bad_encoding = bytes.clone();
+    bad_encoding[2 + 3*33 + 2*8 + 1 + 8 + 2*32 + 2*8 + 6*(33 + 33)] = 2;
+    assert!(deserialize::<RevelioProof>(&bad_encoding).err() == Some(Error::InvalidFlag(2)));
+
+    let mut grin_exch = SimpleGrinExchange::new(3, &mut rng).unwrap();
+    let simple_proof = grin_exch.generate_proof(&mut rng).unwrap();
+    let bytes = serialize(&simple_proof);
+    let decoded: SimpleProof = deserialize(&bytes).unwrap();
+    assert!(decoded.verify().is_ok());
+    assert!(serialize(&decoded) == bytes);
+  }
+
+  #[test]
+  fn reject_malformed_encodings() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let mut grin_exch = RevelioGrinExchange::new(2, 1, &mut rng).unwrap();
+    let bytes = serialize(&grin_exch.generate_proof(&mut rng).unwrap());
+
+    // Truncated input and trailing bytes
+    assert!(deserialize::<RevelioProof>(&bytes[..20]).err() == Some(Error::UnexpectedEof));
+    assert!(deserialize::<RevelioProof>(&bytes[..bytes.len()-1]).is_err());
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(deserialize::<RevelioProof>(&longer).err() == Some(Error::TrailingBytes(1)));
+
+    // Wrong version and wrong proof type
+    let mut wrong_version = bytes.clone();
+    wrong_version[0] = PROTOCOL_VERSION + 1;
+    assert!(deserialize::<RevelioProof>(&wrong_version).err() == Some(Error::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+    assert!(deserialize::<SimpleProof>(&bytes).is_err());
+
+    // G encoded with an invalid prefix and with an x-coordinate not on the curve
+    let mut bad_point = bytes.clone();
+    bad_point[2] = 0x04;
+    assert!(deserialize::<RevelioProof>(&bad_point).err() == Some(Error::InvalidPoint));
+    let mut bad_point = bytes.clone();
+    bad_point[3..35].copy_from_slice(&[0u8; 32]);
+    assert!(deserialize::<RevelioProof>(&bad_point).err() == Some(Error::InvalidPoint));
+
+    // Exchange id which is not valid UTF-8
+    let mut grin_exch = RevelioGrinExchange::new(2, 1, &mut rng).unwrap();
+    grin_exch.set_context(ProofContext { exchange_id: "x".to_string(), block_height: 7 });
+    let mut bad_string = serialize(&grin_exch.generate_proof(&mut rng).unwrap());
+    bad_string[2 + 3*33 + 8] = 0xff;
+    assert!(deserialize::<RevelioProof>(&bad_string).err() == Some(Error::InvalidString));
+
+    // Nonce presence flag which is neither 0 nor 1
+    let mut bad_flag = bytes.clone();
+    bad_flag[2 + 3*33 + 2*8] = 2;
+    assert!(deserialize::<RevelioProof>(&bad_flag).err() == Some(Error::InvalidFlag(2)));
+
+    // Challenge of the reserve proof set to the group order, after an empty
+    // exchange id, the block height and an absent nonce
+    let reserve_start = 2 + 3*33 + 2*8 + 1 + 8;
+    let mut bad_scalar = bytes.clone();
+    bad_scalar[reserve_start..reserve_start + 32].copy_from_slice(&CURVE_ORDER);
+    assert!(deserialize::<RevelioProof>(&bad_scalar).err() == Some(Error::NonCanonicalScalar));
+
+    // Anonymity list length which exceeds the input
+    let mut bad_len = bytes;
+    bad_len[reserve_start + 2*32..reserve_start + 2*32 + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    assert!(deserialize::<RevelioProof>(&bad_len).err() == Some(Error::InvalidLength(u64::MAX)));
+  }
+}
@@ -0,0 +1,729 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::mem;
+use secp256k1zkp as secp;
+use secp::key::PublicKey;
+
+use super::context::secp_context;
+use super::error::Error;
+use super::exchange::{RevelioProof, ProofContext, Generators, RevelioStatementHasher, subtract_amount, is_identity};
+use super::nizk::{RevelioPoK, DiscreteLogPoK, PoKEncoding};
+use super::parallel;
+use super::ser::{self, Readable, Reader, Writeable, Writer, SCALAR_SIZE, POINT_SIZE};
+use super::ser::REVELIO_STREAM_TAG;
+
+/// Number of records a StreamingVerifier holds before verifying them
+pub const CHUNK_SIZE: usize = 1024;
+
+/// Largest encoded stream header accepted by StreamReader
+pub const MAX_HEADER_SIZE: u64 = 1 << 20;
+
+/// Number of outputs, and of key images, a StreamingVerifier holds in memory
+/// before sorting them and writing them to a temporary file
+pub const RUN_SIZE: usize = 1 << 16;
+
+/// Number of sorted runs on disk which are merged into one longer run
+pub const MERGE_WIDTH: usize = 64;
+
+/// Everything in a RevelioProof except the three lists, which in a stream
+/// follow the header one output at a time.
+///
+/// The output proofs depend on the statement digest, which depends on every
+/// output. The header therefore announces the digest, the records are
+/// verified against it, and StreamingVerifier::finish checks that it is the
+/// digest of the records which were actually received.
+#[derive(Clone)]
+pub struct RevelioStreamHeader {
+  pub blinding_basepoint: PublicKey,
+  pub value_basepoint: PublicKey,
+  pub keyimage_basepoint: PublicKey,
+  pub context: ProofContext,
+  pub nonce: Option<Vec<u8>>,
+  pub total_amount: u64,
+  pub reserve_spk: DiscreteLogPoK,
+  pub encoding: PoKEncoding,
+  pub anon_list_size: u64,
+  pub statement_digest: [u8; 32],
+}
+
+impl From<&RevelioProof> for RevelioStreamHeader {
+  fn from(proof: &RevelioProof) -> RevelioStreamHeader {
+    RevelioStreamHeader {
+      blinding_basepoint: proof.blinding_basepoint,
+      value_basepoint: proof.value_basepoint,
+      keyimage_basepoint: proof.keyimage_basepoint,
+      context: proof.context.clone(),
+      nonce: proof.nonce.clone(),
+      total_amount: proof.total_amount,
+      reserve_spk: proof.reserve_spk.clone(),
+      encoding: proof.written_encoding(),
+      anon_list_size: proof.anon_list.len() as u64,
+      statement_digest: proof.statement_digest(),
+    }
+  }
+}
+
+/// Binary layout: G, H, G', context, nonce, total_amount, reserve_spk,
+/// encoding, anon_list_size, statement digest
+impl Writeable for RevelioStreamHeader {
+  fn write(&self, writer: &mut Writer) {
+    writer.write_point(&self.blinding_basepoint);
+    writer.write_point(&self.value_basepoint);
+    writer.write_point(&self.keyimage_basepoint);
+    self.context.write(writer);
+    match self.nonce {
+      Some(ref nonce) => {
+        writer.write_u8(1);
+        writer.write_var_bytes(nonce);
+      },
+      None => writer.write_u8(0),
+    }
+    writer.write_u64(self.total_amount);
+    self.reserve_spk.write(writer);
+    writer.write_u8(self.encoding.to_u8());
+    writer.write_u64(self.anon_list_size);
+    writer.write_fixed_bytes(&self.statement_digest);
+  }
+}
+
+impl Readable for RevelioStreamHeader {
+  fn read(reader: &mut Reader) -> Result<RevelioStreamHeader, ser::Error> {
+    let blinding_basepoint = reader.read_point()?;
+    let value_basepoint = reader.read_point()?;
+    let keyimage_basepoint = reader.read_point()?;
+    let context = ProofContext::read(reader)?;
+    let nonce = match reader.read_u8()? {
+      0 => None,
+      1 => Some(reader.read_var_bytes()?),
+      flag => return Err(ser::Error::InvalidFlag(flag)),
+    };
+    let total_amount = reader.read_u64()?;
+    let reserve_spk = DiscreteLogPoK::read(reader)?;
+    let encoding = PoKEncoding::from_u8(reader.read_u8()?)?;
+    let anon_list_size = reader.read_u64()?;
+    let statement_digest = reader.read_fixed_bytes()?;
+
+    Ok(RevelioStreamHeader {
+      blinding_basepoint,
+      value_basepoint,
+      keyimage_basepoint,
+      context,
+      nonce,
+      total_amount,
+      reserve_spk,
+      encoding,
+      anon_list_size,
+      statement_digest,
+    })
+  }
+}
+
+/// An output of the anonymity list together with its key image and proof
+#[derive(Clone)]
+pub struct OutputRecord {
+  pub output: PublicKey,
+  pub keyimage: PublicKey,
+  pub spk: RevelioPoK,
+}
+
+impl OutputRecord {
+  /// Size of an encoded record, which is fixed for a given encoding
+  pub fn encoded_size(encoding: PoKEncoding) -> usize {
+    match encoding {
+      PoKEncoding::Challenge => 2 * POINT_SIZE + 5 * SCALAR_SIZE,
+      PoKEncoding::Commitment => 5 * POINT_SIZE + 5 * SCALAR_SIZE,
+    }
+  }
+
+  fn write(&self, writer: &mut Writer, encoding: PoKEncoding) {
+    writer.write_point(&self.output);
+    writer.write_point(&self.keyimage);
+    self.spk.write(writer);
+    if let (PoKEncoding::Commitment, Some(commitments)) = (encoding, self.spk.commitments()) {
+      commitments.write(writer);
+    }
+  }
+
+  fn read(reader: &mut Reader, encoding: PoKEncoding) -> Result<OutputRecord, ser::Error> {
+    let output = reader.read_point()?;
+    let keyimage = reader.read_point()?;
+    let spk = match encoding {
+      PoKEncoding::Challenge => RevelioPoK::read(reader)?,
+      PoKEncoding::Commitment => RevelioPoK::read_with_commitments(reader)?,
+    };
+    Ok(OutputRecord { output, keyimage, spk })
+  }
+}
+
+/// Writes a proof as a stream: version, tag, header length, header and one
+/// record per output
+pub fn write_stream<W: Write>(proof: &RevelioProof, mut out: W) -> Result<(), Error> {
+  let header = RevelioStreamHeader::from(proof);
+  let mut writer = Writer::new();
+  writer.write_header(REVELIO_STREAM_TAG);
+  writer.write_var_bytes(&ser::serialize(&header));
+  out.write_all(&writer.into_bytes())?;
+
+  for i in 0..proof.anon_list.len() {
+    let record = OutputRecord {
+      output: proof.anon_list[i],
+      keyimage: proof.keyimage_list[i],
+      spk: proof.spk_list[i].clone(),
+    };
+    let mut writer = Writer::new();
+    record.write(&mut writer, header.encoding);
+    out.write_all(&writer.into_bytes())?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+/// Reads the records of a proof stream one at a time. The iterator ends
+/// after the number of records announced in the header, with an error if
+/// more bytes follow.
+pub struct StreamReader<R: Read> {
+  reader: R,
+  header: RevelioStreamHeader,
+  record_buf: Vec<u8>,
+  records_read: u64,
+  done: bool,
+}
+
+impl<R: Read> StreamReader<R> {
+  /// Reads and decodes the stream header
+  pub fn new(mut reader: R) -> Result<StreamReader<R>, Error> {
+    let mut prefix = [0u8; 10];
+    reader.read_exact(&mut prefix)?;
+    let mut prefix_reader = Reader::new(&prefix);
+    prefix_reader.read_header(REVELIO_STREAM_TAG)?;
+    let header_size = prefix_reader.read_u64()?;
+    if header_size > MAX_HEADER_SIZE {
+      return Err(Error::Ser(ser::Error::InvalidLength(header_size)));
+    }
+
+    let mut header_buf = vec![0u8; header_size as usize];
+    reader.read_exact(&mut header_buf)?;
+    let header: RevelioStreamHeader = ser::deserialize(&header_buf)?;
+
+    Ok(StreamReader {
+      reader,
+      record_buf: vec![0u8; OutputRecord::encoded_size(header.encoding)],
+      header,
+      records_read: 0,
+      done: false,
+    })
+  }
+
+  pub fn header(&self) -> &RevelioStreamHeader {
+    &self.header
+  }
+
+  fn read_record(&mut self) -> Result<OutputRecord, Error> {
+    self.reader.read_exact(&mut self.record_buf)?;
+    let mut reader = Reader::new(&self.record_buf);
+    let record = OutputRecord::read(&mut reader, self.header.encoding)?;
+    reader.finish()?;
+    Ok(record)
+  }
+}
+
+impl<R: Read> Iterator for StreamReader<R> {
+  type Item = Result<OutputRecord, Error>;
+
+  fn next(&mut self) -> Option<Result<OutputRecord, Error>> {
+    if self.done {
+      return None;
+    }
+    if self.records_read == self.header.anon_list_size {
+      self.done = true;
+      return match io::copy(&mut self.reader, &mut io::sink()) {
+        Ok(0) => None,
+        Ok(n) => Some(Err(Error::Ser(ser::Error::TrailingBytes(n as usize)))),
+        Err(e) => Some(Err(e.into())),
+      };
+    }
+
+    let record = self.read_record();
+    self.records_read += 1;
+    self.done = record.is_err();
+    Some(record)
+  }
+}
+
+/// A compressed point together with its index in the list
+type Entry = ([u8; POINT_SIZE], u64);
+
+const ENTRY_SIZE: usize = POINT_SIZE + 8;
+
+/// A sorted sequence of entries, read from memory or from a file
+type Run = Box<dyn Iterator<Item = io::Result<Entry>>>;
+
+fn write_entry<W: Write>(out: &mut W, entry: &Entry) -> io::Result<()> {
+  out.write_all(&entry.0)?;
+  out.write_all(&entry.1.to_be_bytes())
+}
+
+fn read_run(mut file: File) -> io::Result<Run> {
+  file.seek(SeekFrom::Start(0))?;
+  let mut reader = BufReader::new(file);
+  Ok(Box::new(iter::from_fn(move || {
+    let mut buf = [0u8; ENTRY_SIZE];
+    match reader.read_exact(&mut buf) {
+      Ok(()) => {
+        let mut point = [0u8; POINT_SIZE];
+        let mut index = [0u8; 8];
+        point.copy_from_slice(&buf[..POINT_SIZE]);
+        index.copy_from_slice(&buf[POINT_SIZE..]);
+        Some(Ok((point, u64::from_be_bytes(index))))
+      },
+      Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+      Err(e) => Some(Err(e)),
+    }
+  })))
+}
+
+/// Passes the entries of all runs to `f` in sorted order
+fn merge_runs<F>(mut runs: Vec<Run>, mut f: F) -> io::Result<()>
+where
+  F: FnMut(Entry) -> io::Result<()>,
+{
+  let mut heap = BinaryHeap::with_capacity(runs.len());
+  for (r, run) in runs.iter_mut().enumerate() {
+    if let Some(entry) = run.next() {
+      heap.push(Reverse((entry?, r)));
+    }
+  }
+  while let Some(Reverse((entry, r))) = heap.pop() {
+    if let Some(next) = runs[r].next() {
+      heap.push(Reverse((next?, r)));
+    }
+    f(entry)?;
+  }
+  Ok(())
+}
+
+/// The points of a list which arrives one point at a time, kept as sorted
+/// runs in temporary files so that repeated points can be found without
+/// holding the list in memory.
+///
+/// At most `run_size` entries are held in memory. Full runs are written to
+/// disk, and every `merge_width` runs of the same length are merged into one,
+/// so that finding duplicates never reads from more than `merge_width` files
+/// per run length at once.
+struct PointRuns {
+  run_size: usize,
+  merge_width: usize,
+  buffer: Vec<Entry>,
+  levels: Vec<Vec<File>>,
+}
+
+impl PointRuns {
+  fn new(run_size: usize, merge_width: usize) -> PointRuns {
+    PointRuns {
+      run_size,
+      merge_width,
+      buffer: Vec::new(),
+      levels: Vec::new(),
+    }
+  }
+
+  fn push(&mut self, point: &PublicKey, index: usize) -> io::Result<()> {
+    let mut bytes = [0u8; POINT_SIZE];
+    bytes.copy_from_slice(&point.serialize_vec(secp_context(), true));
+    self.buffer.push((bytes, index as u64));
+    if self.buffer.len() == self.run_size {
+      self.buffer.sort_unstable();
+      let mut file = tempfile::tempfile()?;
+      {
+        let mut out = BufWriter::new(&mut file);
+        for entry in self.buffer.iter() {
+          write_entry(&mut out, entry)?;
+        }
+        out.flush()?;
+      }
+      self.buffer.clear();
+      self.add_run(0, file)?;
+    }
+    Ok(())
+  }
+
+  fn add_run(&mut self, level: usize, file: File) -> io::Result<()> {
+    if self.levels.len() == level {
+      self.levels.push(Vec::new());
+    }
+    self.levels[level].push(file);
+    if self.levels[level].len() == self.merge_width {
+      let runs = mem::take(&mut self.levels[level]).into_iter()
+                   .map(read_run)
+                   .collect::<io::Result<Vec<Run>>>()?;
+      let mut merged = tempfile::tempfile()?;
+      {
+        let mut out = BufWriter::new(&mut merged);
+        merge_runs(runs, |entry| write_entry(&mut out, &entry))?;
+        out.flush()?;
+      }
+      self.add_run(level + 1, merged)?;
+    }
+    Ok(())
+  }
+
+  /// Returns the indices of the first repetition of a point in the list,
+  /// i.e. the point whose second occurrence comes earliest, as the indices of
+  /// its first and second occurrence
+  fn first_duplicate(&mut self) -> io::Result<Option<(usize, usize)>> {
+    let mut buffer = mem::take(&mut self.buffer);
+    buffer.sort_unstable();
+    let mut runs: Vec<Run> = vec![Box::new(buffer.into_iter().map(Ok))];
+    for file in mem::take(&mut self.levels).into_iter().flatten() {
+      runs.push(read_run(file)?);
+    }
+
+    // Equal points are adjacent and ordered by index, so the first two
+    // entries of each group are its first and second occurrence
+    let mut duplicate: Option<(u64, u64)> = None;
+    let mut group: Option<([u8; POINT_SIZE], u64, bool)> = None;
+    merge_runs(runs, |(point, index)| {
+      match group {
+        Some((prev, first, false)) if prev == point => {
+          if duplicate.is_none_or(|(_, second)| index < second) {
+            duplicate = Some((first, index));
+          }
+          group = Some((prev, first, true));
+        },
+        Some((prev, _, true)) if prev == point => {},
+        _ => group = Some((point, index, false)),
+      }
+      Ok(())
+    })?;
+    Ok(duplicate.map(|(first, second)| (first as usize, second as usize)))
+  }
+}
+
+/// Verifies a proof whose outputs arrive one at a time.
+///
+/// Memory use does not grow with the number of outputs. Only the last
+/// CHUNK_SIZE records are held in full. Repeated outputs and key images are
+/// found exactly by keeping the points in sorted runs of RUN_SIZE entries,
+/// which are written to temporary files and merged by finish. Disk use is
+/// about 41 bytes per output and per key image.
+pub struct StreamingVerifier {
+  header: RevelioStreamHeader,
+  gens: Generators,
+  output_runs: PointRuns,
+  keyimage_runs: PointRuns,
+  statement_hasher: RevelioStatementHasher,
+  sum_keyimages: Option<PublicKey>,
+  chunk_start: usize,
+  chunk_outputs: Vec<PublicKey>,
+  chunk_keyimages: Vec<PublicKey>,
+  chunk_spks: Vec<RevelioPoK>,
+  num_records: usize,
+}
+
+impl StreamingVerifier {
+  /// Starts verifying a proof stream against the canonical generators
+  pub fn new(header: RevelioStreamHeader) -> Result<StreamingVerifier, Error> {
+    StreamingVerifier::with_generators(header, Generators::new())
+  }
+
+  /// Starts verifying a proof stream, rejecting it if its basepoints differ
+  /// from `gens`
+  pub fn with_generators(header: RevelioStreamHeader, gens: Generators) -> Result<StreamingVerifier, Error> {
+    if header.anon_list_size == 0 {
+      return Err(Error::EmptyList);
+    }
+    if header.blinding_basepoint != gens.blinding_gen ||
+       header.value_basepoint != gens.value_gen ||
+       header.keyimage_basepoint != gens.keyimage_gen {
+      return Err(Error::NonCanonicalGenerators);
+    }
+
+    let statement_hasher = RevelioStatementHasher::new(
+      &header.blinding_basepoint,
+      &header.value_basepoint,
+      &header.keyimage_basepoint,
+      &header.context,
+      header.total_amount,
+      header.anon_list_size,
+    );
+
+    Ok(StreamingVerifier {
+      header,
+      gens,
+      output_runs: PointRuns::new(RUN_SIZE, MERGE_WIDTH),
+      keyimage_runs: PointRuns::new(RUN_SIZE, MERGE_WIDTH),
+      statement_hasher,
+      sum_keyimages: None,
+      chunk_start: 0,
+      chunk_outputs: Vec::with_capacity(CHUNK_SIZE),
+      chunk_keyimages: Vec::with_capacity(CHUNK_SIZE),
+      chunk_spks: Vec::with_capacity(CHUNK_SIZE),
+      num_records: 0,
+    })
+  }
+
+  pub fn header(&self) -> &RevelioStreamHeader {
+    &self.header
+  }
+
+  /// Number of records pushed so far
+  pub fn num_records(&self) -> usize {
+    self.num_records
+  }
+
+  /// Adds the next record. Its proof is verified once CHUNK_SIZE records
+  /// have been collected, or at the latest by finish. Repeated outputs and
+  /// key images are reported by finish.
+  pub fn push(&mut self, record: OutputRecord) -> Result<(), Error> {
+    let index = self.num_records;
+    if index as u64 >= self.header.anon_list_size {
+      return Err(Error::ListSizeMismatch {
+        expected: self.header.anon_list_size as usize,
+        found: index + 1,
+      });
+    }
+
+    if is_identity(&record.output) {
+      return Err(Error::IdentityOutput(index));
+    }
+    if is_identity(&record.keyimage) {
+      return Err(Error::IdentityKeyImage(index));
+    }
+
+    self.output_runs.push(&record.output, index)?;
+    self.keyimage_runs.push(&record.keyimage, index)?;
+
+    self.statement_hasher.add_output(&record.output, &record.keyimage);
+    self.sum_keyimages = Some(match self.sum_keyimages {
+      Some(sum) => PublicKey::from_combination(secp_context(), vec![&sum, &record.keyimage])?,
+      None => record.keyimage,
+    });
+
+    self.chunk_outputs.push(record.output);
+    self.chunk_keyimages.push(record.keyimage);
+    self.chunk_spks.push(record.spk);
+    self.num_records += 1;
+
+    if self.chunk_outputs.len() == CHUNK_SIZE {
+      self.verify_chunk()?;
+    }
+    Ok(())
+  }
+
+  /// Verifies the records collected since the last chunk against the
+  /// announced statement digest
+  fn verify_chunk(&mut self) -> Result<(), Error> {
+    if self.chunk_outputs.is_empty() {
+      return Ok(());
+    }
+    let secp_inst = secp_context();
+    let gens = &self.gens;
+    let statement = &self.header.statement_digest;
+    let nonce = self.header.nonce.as_deref();
+
+    let batch_valid = self.header.encoding == PoKEncoding::Commitment &&
+                      RevelioPoK::batch_verify_spk(
+                        secp_inst,
+                        &self.chunk_outputs,
+                        &self.chunk_keyimages,
+                        &self.chunk_spks,
+                        &gens.blinding_gen,
+                        &gens.value_gen,
+                        &gens.keyimage_gen,
+                        statement,
+                        nonce,
+                      )?;
+
+    if !batch_valid {
+      let results = parallel::map_indices(self.chunk_outputs.len(), |i| {
+        RevelioPoK::verify_spk(
+          secp_inst,
+          &self.chunk_outputs[i],
+          &self.chunk_keyimages[i],
+          &gens.blinding_gen,
+          &gens.value_gen,
+          &gens.keyimage_gen,
+          statement,
+          nonce,
+          &self.chunk_spks[i],
+        )
+      });
+      for (i, result) in results.into_iter().enumerate() {
+        if !result? {
+          return Err(Error::InvalidOutputProof(self.chunk_start + i));
+        } // end if
+      } // end for
+    } // end if
+
+    self.chunk_start += self.chunk_outputs.len();
+    self.chunk_outputs.clear();
+    self.chunk_keyimages.clear();
+    self.chunk_spks.clear();
+    Ok(())
+  }
+
+  /// Checks that no output or key image is repeated, verifies the remaining
+  /// records, checks that the records match the announced statement digest
+  /// and verifies the proof of the total amount
+  pub fn finish(mut self) -> Result<RevelioStreamHeader, Error> {
+    // A repeated output or key image would count the same coins twice
+    if let Some((first, second)) = self.output_runs.first_duplicate()? {
+      return Err(Error::DuplicateOutput { first, second });
+    }
+    if let Some((first, second)) = self.keyimage_runs.first_duplicate()? {
+      return Err(Error::DuplicateKeyImage { first, second });
+    }
+
+    self.verify_chunk()?;
+    if self.num_records as u64 != self.header.anon_list_size {
+      return Err(Error::ListSizeMismatch {
+        expected: self.header.anon_list_size as usize,
+        found: self.num_records,
+      });
+    }
+
+    if self.statement_hasher.finish() != self.header.statement_digest {
+      return Err(Error::StatementMismatch);
+    }
+
+    // Check that sum(I_i) - total_amount*H = y*G' for a y known to the exchange
+    let secp_inst = secp_context();
+    let sum_keyimages = self.sum_keyimages.ok_or(Error::EmptyList)?;
+    let reserve_point = subtract_amount(secp_inst, sum_keyimages, self.header.total_amount, self.gens.value_gen)?;
+    if !DiscreteLogPoK::verify_dlog_spk(
+      secp_inst,
+      &reserve_point,
+      &self.gens.keyimage_gen,
+      &self.header.statement_digest,
+      self.header.nonce.as_deref(),
+      &self.header.reserve_spk,
+    )? {
+      return Err(Error::InvalidReserveProof);
+    }
+
+    Ok(self.header)
+  }
+}
+
+/// Verifies a proof given as a header and its records
+pub fn verify_records<I>(header: RevelioStreamHeader, records: I) -> Result<RevelioStreamHeader, Error>
+where
+  I: IntoIterator<Item = Result<OutputRecord, Error>>,
+{
+  let mut verifier = StreamingVerifier::new(header)?;
+  for record in records {
+    verifier.push(record?)?;
+  }
+  verifier.finish()
+}
+
+/// Verifies a proof written by write_stream, reading it from `reader` and
+/// returning its header if it is valid
+pub fn verify_stream<R: Read>(reader: R) -> Result<RevelioStreamHeader, Error> {
+  let mut stream = StreamReader::new(reader)?;
+  let header = stream.header().clone();
+  verify_records(header, &mut stream)
+}
+
+#[cfg(test)]
+mod test {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+  use secp256k1zkp::key::{PublicKey, SecretKey};
+  use super::{write_stream, verify_stream, verify_records, StreamReader, PointRuns, CHUNK_SIZE};
+  use crate::context::secp_context;
+  use crate::error::Error;
+  use crate::exchange::{RevelioGrinExchange, RevelioProof};
+  use crate::nizk::PoKEncoding;
+  use crate::ser;
+
+  fn stream_bytes(proof: &RevelioProof) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_stream(proof, &mut bytes).unwrap();
+    bytes
+  }
+
+  #[test]
+  fn stream_verifies_in_both_encodings() {
+    let mut rng = StdRng::seed_from_u64(19);
+    let mut grin_exch = RevelioGrinExchange::new(CHUNK_SIZE + 10, 5, &mut rng).unwrap();
+    let mut proof = grin_exch.generate_proof(&mut rng).unwrap();
+    assert!(proof.verify().is_ok());
+
+    for encoding in [PoKEncoding::Challenge, PoKEncoding::Commitment] {
+      proof.set_encoding(encoding).unwrap();
+      let bytes = stream_bytes(&proof);
+      let header = verify_stream(&bytes[..]).unwrap();
+      assert!(header.statement_digest == proof.statement_digest());
+      assert!(header.total_amount == proof.total_amount);
+
+      // An output proof past the first chunk is reported at its own index
+      let mut tampered = proof.clone();
+      tampered.spk_list.swap(CHUNK_SIZE + 1, CHUNK_SIZE + 2);
+      let bytes = stream_bytes(&tampered);
+      assert!(verify_stream(&bytes[..]).err() == Some(Error::InvalidOutputProof(CHUNK_SIZE + 1)));
+
+      // Truncated and extended streams are rejected
+      let mut bytes = stream_bytes(&proof);
+      assert!(verify_stream(&bytes[..bytes.len() - 1]).err() == Some(Error::Ser(ser::Error::UnexpectedEof)));
+      bytes.push(0);
+      assert!(verify_stream(&bytes[..]).err() == Some(Error::Ser(ser::Error::TrailingBytes(1))));
+    }
+  }
+
+  #[test]
+  fn records_must_match_announced_statement() {
+    let mut rng = StdRng::seed_from_u64(20);
+    let mut grin_exch = RevelioGrinExchange::new(20, 3, &mut rng).unwrap();
+    let proof = grin_exch.generate_proof(&mut rng).unwrap();
+    let bytes = stream_bytes(&proof);
+    let records = || StreamReader::new(&bytes[..]).unwrap();
+
+    // The output proofs verify against the announced digest, but the header
+    // declares another total than the digest commits to
+    let mut header = records().header().clone();
+    header.total_amount += 1;
+    assert!(verify_records(header, records()).err() == Some(Error::StatementMismatch));
+
+    // Dropping a record is noticed although every remaining one verifies
+    let header = records().header().clone();
+    assert!(verify_records(header, records().skip(1)).err() == Some(Error::ListSizeMismatch { expected: 20, found: 19 }));
+
+    // A repeated key image is caught without holding the key image list
+    let header = records().header().clone();
+    let mut duplicated: Vec<_> = records().collect();
+    let keyimage = duplicated[4].as_ref().unwrap().keyimage;
+    duplicated[9].as_mut().unwrap().keyimage = keyimage;
+    assert!(verify_records(header, duplicated).err() == Some(Error::DuplicateKeyImage { first: 4, second: 9 }));
+  }
+
+  #[test]
+  fn point_runs_find_first_repetition_across_files() {
+    let mut rng = StdRng::seed_from_u64(21);
+    let secp_inst = secp_context();
+    let mut points: Vec<PublicKey> = (0..50).map(|_| {
+      PublicKey::from_secret_key(secp_inst, &SecretKey::new(secp_inst, &mut rng)).unwrap()
+    }).collect();
+
+    // Runs of 3 points merged 2 at a time spread the list over several files
+    let first_duplicate = |points: &[PublicKey]| {
+      let mut runs = PointRuns::new(3, 2);
+      for (i, point) in points.iter().enumerate() {
+        runs.push(point, i).unwrap();
+      }
+      assert!(runs.levels.len() > 2);
+      runs.first_duplicate().unwrap()
+    };
+    assert!(first_duplicate(&points).is_none());
+
+    // The repetition whose second occurrence comes first is reported, with
+    // the first occurrence of its point
+    points[40] = points[7];
+    points[31] = points[12];
+    points[45] = points[12];
+    assert!(first_duplicate(&points) == Some((12, 31)));
+  }
+}
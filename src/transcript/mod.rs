@@ -0,0 +1,306 @@
+use digest::Digest;
+use sha2::Sha512;
+use rand::{RngCore, CryptoRng};
+use secp256k1zkp as secp;
+use secp::Secp256k1;
+use secp::key::{SecretKey, PublicKey};
+
+use super::ser::{CURVE_ORDER, SCALAR_SIZE, PROTOCOL_VERSION};
+
+/// Name of the protocol which every transcript starts with
+pub const PROTOCOL_NAME: &[u8] = b"Revelio";
+
+/// Size of the input to reduce_wide
+pub const WIDE_SIZE: usize = 2 * SCALAR_SIZE;
+
+/// 2^256 - n as little-endian 64-bit limbs, so that 2^256 = ORDER_COMPLEMENT mod n
+const ORDER_COMPLEMENT: [u64; 3] = [0x402da1732fc9bebf, 0x4551231950b75fc4, 0x1];
+
+/// Converts big-endian bytes to little-endian 64-bit limbs
+fn to_limbs(bytes: &[u8]) -> Vec<u64> {
+  bytes.rchunks(8).map(|chunk| {
+    let mut limb = [0u8; 8];
+    limb[8 - chunk.len()..].copy_from_slice(chunk);
+    u64::from_be_bytes(limb)
+  }).collect()
+}
+
+/// Calculates a*b + c on little-endian limbs
+fn mul_add(a: &[u64], b: &[u64], c: &[u64]) -> Vec<u64> {
+  let mut result = vec![0u64; (a.len() + b.len()).max(c.len()) + 1];
+  result[..c.len()].copy_from_slice(c);
+  for (i, &ai) in a.iter().enumerate() {
+    let mut carry = 0u128;
+    for (j, &bj) in b.iter().enumerate() {
+      let t = (ai as u128) * (bj as u128) + (result[i + j] as u128) + carry;
+      result[i + j] = t as u64;
+      carry = t >> 64;
+    }
+    let mut k = i + b.len();
+    while carry != 0 {
+      let t = (result[k] as u128) + carry;
+      result[k] = t as u64;
+      carry = t >> 64;
+      k += 1;
+    }
+  }
+  result
+}
+
+/// Reduces a 512-bit big-endian number modulo the group order n.
+///
+/// Reducing 512 bits instead of 256 makes the bias of the result negligible,
+/// and unlike SecretKey::from_slice it never fails. The result is zero if the
+/// input is a multiple of n.
+pub fn reduce_wide(bytes: &[u8; WIDE_SIZE]) -> SecretKey {
+  let order = to_limbs(&CURVE_ORDER);
+  let mut limbs = to_limbs(bytes);
+
+  // Fold the bits above 2^256 back in using 2^256 = ORDER_COMPLEMENT mod n
+  while limbs[4..].iter().any(|&limb| limb != 0) {
+    let (low, high) = limbs.split_at(4);
+    limbs = mul_add(high, &ORDER_COMPLEMENT, low);
+  }
+  limbs.truncate(4);
+
+  // Now limbs < 2^256 < 2n, so at most one subtraction is needed
+  if limbs.iter().rev().cmp(order.iter().rev()) != std::cmp::Ordering::Less {
+    let mut borrow = 0u64;
+    for (limb, &order_limb) in limbs.iter_mut().zip(order.iter()) {
+      let (t, b1) = limb.overflowing_sub(order_limb);
+      let (t, b2) = t.overflowing_sub(borrow);
+      *limb = t;
+      borrow = (b1 || b2) as u64;
+    }
+  }
+
+  let mut scalar = [0u8; SCALAR_SIZE];
+  for (i, limb) in limbs.iter().rev().enumerate() {
+    scalar[8*i..8*i + 8].copy_from_slice(&limb.to_be_bytes());
+  }
+  SecretKey(scalar)
+}
+
+/// How a prover derives its random scalars, i.e. the commitment randomness
+/// and the simulated challenges and responses of an OR-proof
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum NonceMode {
+  #[default]
+  /// Derived from the transcript, the witness and fresh bytes from the RNG.
+  /// A weak RNG alone cannot leak the witness.
+  Random,
+  /// Derived from the transcript and the witness only, in the style of
+  /// RFC 6979. The same inputs always give the same proof and the RNG is not
+  /// used.
+  Deterministic,
+}
+
+/// Source of a prover's random scalars, created by Transcript::nonce_generator
+pub struct NonceGenerator {
+  seed: [u8; WIDE_SIZE],
+}
+
+impl NonceGenerator {
+  /// Derives the scalar with the given label
+  pub fn scalar(&self, label: &[u8]) -> SecretKey {
+    let mut hasher = Sha512::new();
+    for bytes in &[&self.seed[..], label] {
+      hasher.input((bytes.len() as u64).to_be_bytes());
+      hasher.input(bytes);
+    }
+    let mut wide = [0u8; WIDE_SIZE];
+    wide.copy_from_slice(&hasher.result());
+    reduce_wide(&wide)
+  }
+}
+
+/// Fiat-Shamir transcript from which the challenges of the sigma proofs are
+/// derived.
+///
+/// A transcript starts with the protocol name, the protocol version and the
+/// name of the proof. Every later append is framed as
+/// len(label) || label || len(data) || data with lengths as 8 big-endian
+/// bytes, so two different sequences of appends never hash the same input.
+#[derive(Clone)]
+pub struct Transcript<'a> {
+  secp_inst: &'a Secp256k1,
+  hasher: Sha512,
+}
+
+impl<'a> Transcript<'a> {
+  pub fn new(secp_inst: &'a Secp256k1, proof_name: &[u8]) -> Transcript<'a> {
+    let mut transcript = Transcript {
+      secp_inst,
+      hasher: Sha512::new(),
+    };
+    transcript.append_message(b"protocol", PROTOCOL_NAME);
+    transcript.append_message(b"version", &[PROTOCOL_VERSION]);
+    transcript.append_message(b"proof", proof_name);
+    transcript
+  }
+
+  fn append_framed(&mut self, bytes: &[u8]) {
+    self.hasher.input((bytes.len() as u64).to_be_bytes());
+    self.hasher.input(bytes);
+  }
+
+  pub fn append_message(&mut self, label: &[u8], message: &[u8]) {
+    self.append_framed(label);
+    self.append_framed(message);
+  }
+
+  /// Appends a point in compressed form
+  pub fn append_point(&mut self, label: &[u8], point: &PublicKey) {
+    let bytes = point.serialize_vec(self.secp_inst, true);
+    self.append_message(label, &bytes);
+  }
+
+  /// Appends an optional message, keeping a missing message distinct from
+  /// an empty one
+  pub fn append_option(&mut self, label: &[u8], message: Option<&[u8]>) {
+    match message {
+      Some(message) => {
+        let mut bytes = vec![1u8];
+        bytes.extend_from_slice(message);
+        self.append_message(label, &bytes);
+      },
+      None => self.append_message(label, &[0u8]),
+    }
+  }
+
+  /// Creates the generator of the prover's random scalars from everything
+  /// appended so far and the witness. Nothing is appended to the transcript.
+  pub fn nonce_generator(
+    &self,
+    witness: &[&SecretKey],
+    mode: NonceMode,
+    rng: &mut (impl RngCore + CryptoRng),
+  ) -> NonceGenerator {
+    let mut transcript = self.clone();
+    for secret in witness {
+      transcript.append_message(b"witness", &secret.0);
+    }
+    match mode {
+      NonceMode::Random => {
+        let mut random_bytes = [0u8; 32];
+        rng.fill_bytes(&mut random_bytes);
+        transcript.append_option(b"rng", Some(&random_bytes));
+      },
+      NonceMode::Deterministic => transcript.append_option(b"rng", None),
+    }
+    transcript.append_message(b"nonce_seed", &[]);
+
+    let mut seed = [0u8; WIDE_SIZE];
+    seed.copy_from_slice(&transcript.hasher.result());
+    NonceGenerator { seed }
+  }
+
+  /// Derives the challenge from everything appended so far by reducing the
+  /// 512-bit hash modulo the group order
+  pub fn challenge_scalar(mut self, label: &[u8]) -> SecretKey {
+    self.append_message(b"challenge", label);
+    let mut wide = [0u8; WIDE_SIZE];
+    wide.copy_from_slice(&self.hasher.result());
+    reduce_wide(&wide)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use secp256k1zkp as secp;
+  use secp::Secp256k1;
+  use super::super::ser::CURVE_ORDER;
+  use super::super::nizk::MINUS_ONE_KEY;
+  use super::{Transcript, reduce_wide, WIDE_SIZE};
+
+  /// Places a 256-bit big-endian number in the low half of a wide input
+  fn widen(low: &[u8; 32]) -> [u8; WIDE_SIZE] {
+    let mut wide = [0u8; WIDE_SIZE];
+    wide[32..].copy_from_slice(low);
+    wide
+  }
+
+  fn scalar_from_hex(s: &str) -> [u8; 32] {
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&hex::decode(s).unwrap());
+    scalar
+  }
+
+  fn challenge(appends: &[(&[u8], &[u8])]) -> [u8; 32] {
+    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+    let mut transcript = Transcript::new(&secp_inst, b"test");
+    for (label, message) in appends {
+      transcript.append_message(label, message);
+    }
+    transcript.challenge_scalar(b"c").0
+  }
+
+  #[test]
+  fn reduce_wide_boundaries() {
+    // Zero and small values are unchanged
+    assert!(reduce_wide(&[0u8; WIDE_SIZE]).0 == [0u8; 32]);
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    assert!(reduce_wide(&widen(&one)).0 == one);
+
+    // n-1, n and n+1
+    assert!(reduce_wide(&widen(&MINUS_ONE_KEY.0)).0 == MINUS_ONE_KEY.0);
+    assert!(reduce_wide(&widen(&CURVE_ORDER)).0 == [0u8; 32]);
+    let mut order_plus_one = CURVE_ORDER;
+    order_plus_one[31] += 1;
+    assert!(reduce_wide(&widen(&order_plus_one)).0 == one);
+
+    // 2^256 - 1, which is the largest 256-bit hash output
+    let complement = scalar_from_hex("000000000000000000000000000000014551231950b75fc4402da1732fc9bebe");
+    assert!(reduce_wide(&widen(&[0xffu8; 32])).0 == complement);
+
+    // 2^256, the smallest input with a non-zero high half
+    let mut two_256 = [0u8; WIDE_SIZE];
+    two_256[31] = 1;
+    let mut complement_plus_one = complement;
+    complement_plus_one[31] += 1;
+    assert!(reduce_wide(&two_256).0 == complement_plus_one);
+
+    // (2^256 - 1)*2^256 and 2^512 - 1
+    let mut high_only = [0u8; WIDE_SIZE];
+    high_only[..32].copy_from_slice(&[0xffu8; 32]);
+    assert!(reduce_wide(&high_only).0 ==
+            scalar_from_hex("9d671cd581c69bc5e697f5e45bcd07c52ec373a8bdc598b4493f50a1380e1281"));
+    assert!(reduce_wide(&[0xffu8; WIDE_SIZE]).0 ==
+            scalar_from_hex("9d671cd581c69bc5e697f5e45bcd07c6741496c20e7cf878896cf21467d7d13f"));
+
+    // n*2^256 + n is a multiple of n
+    let mut order_twice = [0u8; WIDE_SIZE];
+    order_twice[..32].copy_from_slice(&CURVE_ORDER);
+    order_twice[32..].copy_from_slice(&CURVE_ORDER);
+    assert!(reduce_wide(&order_twice).0 == [0u8; 32]);
+  }
+
+  #[test]
+  fn framing_separates_appends() {
+    let base = challenge(&[(b"a", b"xy"), (b"b", b"z")]);
+    assert!(base == challenge(&[(b"a", b"xy"), (b"b", b"z")]));
+
+    // Moving bytes between messages, or between a label and its message
+    assert!(base != challenge(&[(b"a", b"x"), (b"b", b"yz")]));
+    assert!(base != challenge(&[(b"ax", b"y"), (b"b", b"z")]));
+    // Same messages under different labels
+    assert!(base != challenge(&[(b"c", b"xy"), (b"b", b"z")]));
+    // Same bytes split into a different number of appends
+    assert!(base != challenge(&[(b"a", b"xy"), (b"b", b""), (b"z", b"")]));
+  }
+
+  #[test]
+  fn proof_name_and_options_separate_transcripts() {
+    let secp_inst = Secp256k1::with_caps(secp::ContextFlag::Commit);
+    let challenge_with = |proof_name: &[u8], nonce: Option<&[u8]>| {
+      let mut transcript = Transcript::new(&secp_inst, proof_name);
+      transcript.append_option(b"nonce", nonce);
+      transcript.challenge_scalar(b"c").0
+    };
+
+    assert!(challenge_with(b"first", None) != challenge_with(b"second", None));
+    assert!(challenge_with(b"first", None) != challenge_with(b"first", Some(b"")));
+    assert!(challenge_with(b"first", Some(b"")) != challenge_with(b"first", Some(&[0u8])));
+  }
+}